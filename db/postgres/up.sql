@@ -7,14 +7,84 @@ CREATE TABLE tasks (
     ts_submitted integer NOT NULL,
     ts_started integer,
     ts_completed integer,
-    problem_capacity integer NOT NULL,
-    problem_weights integer[] NOT NULL,
-    problem_values integer[] NOT NULL
+    problem_capacity bigint NOT NULL,
+    problem_weights bigint[] NOT NULL,
+    problem_values bigint[] NOT NULL,
+    problem_weight_scale integer NOT NULL,
+    problem_value_scale integer NOT NULL,
+    algorithm varchar(20) NOT NULL,
+    epsilon double precision,
+    initial_solution integer[],
+    sensitivity boolean NOT NULL,
+    alternatives integer,
+    optimal_only boolean NOT NULL,
+    tie_break varchar(20),
+    parent_id uuid REFERENCES tasks (id),
+    removed_items integer[],
+    inherited_bound bigint,
+    problem_deviations bigint[],
+    gamma integer,
+    max_items integer,
+    categories text[],
+    category_names text[],
+    category_limits integer[],
+    constraint_coefficients double precision[],
+    constraint_bounds double precision[],
+    keep_best boolean,
+    owner varchar(64),
+    tags text[],
+    idempotency_key varchar(255) UNIQUE,
+    request_hash char(64),
+    callback_url varchar(2048)
 );
 
+-- Tasks are listed by the time they were submitted, optionally filtered by
+-- status, owner or tags
+CREATE INDEX tasks_submitted ON tasks (ts_submitted, id);
+CREATE INDEX tasks_status_submitted ON tasks (status, ts_submitted, id);
+CREATE INDEX tasks_owner_submitted ON tasks (owner, ts_submitted, id);
+CREATE INDEX tasks_tags ON tasks USING gin (tags);
+
 CREATE TABLE solutions (
     id uuid PRIMARY KEY,
     packed_items integer[] NOT NULL,
-    total_value integer NOT NULL,
-    task_id uuid NOT NULL REFERENCES tasks (id)
+    total_value bigint NOT NULL,
+    task_id uuid NOT NULL REFERENCES tasks (id),
+    upper_bound bigint,
+    rank integer NOT NULL,
+    UNIQUE (task_id, rank)
+);
+
+CREATE TABLE sensitivity_reports (
+    task_id uuid PRIMARY KEY REFERENCES tasks (id),
+    optimal_value bigint NOT NULL,
+    capacity_price double precision NOT NULL,
+    reduced_costs double precision[] NOT NULL,
+    value_thresholds bigint[] NOT NULL,
+    extra_capacities bigint[] NOT NULL,
+    capacity_increments bigint[] NOT NULL,
+    capacity_values bigint[] NOT NULL
+);
+
+-- The knapsack of a finished task with a callback url is posted there by the
+-- api, which retries until it's delivered or has failed too often. Each
+-- attempt is logged.
+CREATE TABLE deliveries (
+    task_id uuid PRIMARY KEY REFERENCES tasks (id),
+    callback_url varchar(2048) NOT NULL,
+    status varchar(20) NOT NULL,
+    attempts integer NOT NULL,
+    ts_next_attempt integer NOT NULL,
+    ts_delivered integer
+);
+
+CREATE INDEX deliveries_pending ON deliveries (ts_next_attempt) WHERE status = 'pending';
+
+CREATE TABLE delivery_attempts (
+    task_id uuid NOT NULL REFERENCES deliveries (task_id),
+    attempt integer NOT NULL,
+    ts_attempted integer NOT NULL,
+    response_status integer,
+    error text,
+    PRIMARY KEY (task_id, attempt)
 );
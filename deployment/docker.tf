@@ -1,8 +1,8 @@
 resource "docker_image" "api" {
   name = "api"
   build {
-    context = "../services/api"
-    dockerfile = "../services/rust-1.79-Dockerfile"
+    context = "../services"
+    dockerfile = "rust-1.88-Dockerfile"
     build_arg = {
       APP_NAME : "api"
       DEPENDENCIES : "libpq-dev"
@@ -13,11 +13,13 @@ resource "docker_image" "api" {
 resource "docker_image" "optimizer" {
   name = "api"
   build {
-    context = "../services/optimizer"
-    dockerfile = "../services/rust-1.79-Dockerfile"
+    context = "../services"
+    dockerfile = "rust-1.88-Dockerfile"
     build_arg = {
       APP_NAME : "optimizer"
       DEPENDENCIES : "libpq-dev"
+      FEATURES : "milp"
+      BUILD_DEPENDENCIES : "cmake libclang-dev"
     }
   }
 }
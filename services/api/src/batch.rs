@@ -0,0 +1,109 @@
+//! Submits many problems at once. The body is a JSON array of request bodies
+//! of `POST /knapsack`, as returned by `POST /knapsack/import`. Each of them is
+//! parsed and validated on its own, and the valid ones are submitted together,
+//! while the others are reported with the reason they were rejected.
+
+use rocket::http::Status;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use utoipa::ToSchema;
+
+use crate::dto::{Knapsack, ProblemBody};
+use crate::validation::Limits;
+use crate::{ApiError, ApiResult};
+
+/// Maximum size of a batch, in MiB, and number of problems in it
+pub const MAX_SIZE_MIB: u64 = 64;
+pub const MAX_PROBLEMS: usize = 10_000;
+
+/// The outcome of submitting one problem of the batch, which is either the
+/// submitted knapsack or the error it was rejected with, whose fields are
+/// relative to the problem's request body
+#[derive(Serialize, ToSchema)]
+pub struct BatchResult {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub knapsack: Option<Knapsack>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl BatchResult {
+    pub fn new(knapsack: ApiResult<Knapsack>) -> Self {
+        match knapsack {
+            Ok(knapsack) => Self { status: 200, knapsack: Some(knapsack), error: None },
+            Err(error) => Self { status: error.status().code, knapsack: None, error: Some(error) },
+        }
+    }
+}
+
+/// A knapsack for each valid problem of the batch, in the order they're given
+pub fn prepare(text: &str, limits: &Limits) -> ApiResult<Vec<ApiResult<Knapsack>>> {
+    let bodies: Vec<&RawValue> = serde_json::from_str(text).map_err(|e| ApiError::from_json(&e))?;
+    if bodies.len() > MAX_PROBLEMS {
+        return Err(ApiError::new(Status::PayloadTooLarge, "too_large",
+                                 format!("A batch has at most {} problems", MAX_PROBLEMS)));
+    }
+    Ok(bodies.into_iter()
+        .map(|body| {
+            let problem: ProblemBody = serde_json::from_str(body.get())
+                .map_err(|e| ApiError::from_json(&e))?;
+            problem.validate(limits)?;
+            Ok(Knapsack::new(problem.owned_to_problem()))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The body of the knapsack, or the code and fields of the error of each
+    /// problem
+    fn outcomes(text: &str) -> Vec<serde_json::Value> {
+        prepare(text, &Limits::default()).unwrap().into_iter()
+            .map(BatchResult::new)
+            .map(|result| serde_json::to_value(result).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reports_each_invalid_problem_on_its_own() {
+        let outcomes = outcomes(r#"[
+            {"problem": {"capacity": 60, "weights": [10, 20], "values": [10, 3], "epsilon": 2}},
+            {"problem": {"capacity": 60, "weights": [10, 20], "values": [10, 3]}},
+            {"problem": {"capacity": 60, "weights": [10, 20], "values": [10]}},
+            {"problem": {"capacity": "lots", "weights": [10], "values": [10]}}
+        ]"#);
+        let statuses: Vec<&serde_json::Value> = outcomes.iter().map(|o| &o["status"]).collect();
+        assert_eq!(statuses, [400, 200, 400, 422]);
+        assert_eq!(outcomes[0]["error"]["errors"][0]["field"], "/problem/epsilon");
+        assert_eq!(outcomes[1]["knapsack"]["status"], "submitted");
+        assert_eq!(outcomes[2]["error"]["errors"][0]["field"], "/problem/values");
+        assert_eq!(outcomes[3]["error"]["code"], "invalid_json");
+    }
+
+    #[test]
+    fn keeps_the_order_of_the_problems() {
+        let capacities = [5, 1, 4, 2, 3];
+        let text = serde_json::to_string(&capacities.iter()
+            .map(|c| serde_json::json!({"problem": {"capacity": c, "weights": [1], "values": [1]}}))
+            .collect::<Vec<_>>()).unwrap();
+        let submitted: Vec<serde_json::Value> = outcomes(&text).iter()
+            .map(|o| o["knapsack"]["problem"]["capacity"].clone())
+            .collect();
+        assert_eq!(submitted, capacities);
+    }
+
+    /// A problem which isn't JSON at all can't be told apart from the others,
+    /// so nothing of the batch is submitted
+    #[test]
+    fn rejects_the_whole_batch_if_a_problem_is_malformed() {
+        let text = r#"[{"problem": {"capacity": 60, "weights": [10], "values": [10]}}, {"problem": ]"#;
+        let error = prepare(text, &Limits::default()).err().unwrap();
+        assert_eq!(error.status(), Status::BadRequest);
+
+        let too_many = format!("[{}]", vec!["{}"; MAX_PROBLEMS + 1].join(","));
+        assert_eq!(prepare(&too_many, &Limits::default()).err().unwrap().status(), Status::PayloadTooLarge);
+    }
+}
@@ -12,17 +12,33 @@ use std::sync::MutexGuard;
 
 use backoff::{retry, ExponentialBackoff, Error};
 
-use crate::{get_var, ApiResult, HttpStatus, Knapsack};
+use crate::{get_var, ApiError, ApiResult, Knapsack};
+use crate::dto::{SensitivityReport, Solutions, Status as TaskStatus};
+use crate::list::{Cursor, Filter, TaskList, TaskSummary};
+use crate::idempotency;
+use crate::updates;
+use crate::webhook::DeliveryLog;
+use schema::sensitivity_reports;
 
 pub mod models;
 pub mod schema;
 
 use models::Solution;
 use models::Task;
+use models::{Delivery, DeliveryAttempt};
 
 pub type Connection = Mutex<PgConnection>;
 
+/// Number of tasks inserted per statement, which keeps the number of
+/// parameters below the limit of Postgres (65535)
+const INSERT_CHUNK_SIZE: usize = 1000;
+
 pub async fn init() -> Connection {
+    Mutex::new(connect())
+}
+
+/// A connection of its own, e.g. to listen for notifications on
+pub fn connect() -> PgConnection {
     let user = get_var("DATABASE_USER");
     let pass = get_var("DATABASE_PASS");
     let host = get_var("DATABASE_HOST");
@@ -31,96 +47,453 @@ pub async fn init() -> Connection {
 
     let url = format!("postgres://{}:{}@{}/{}", user, pass, host, db);
 
-    let connection = retry(ExponentialBackoff::default(), || {
-        PgConnection::establish(&url).map_err(Error::transient)}).unwrap();
-
-    Mutex::new(connection)
+    retry(ExponentialBackoff::default(), || {
+        PgConnection::establish(&url).map_err(Error::transient)}).unwrap()
 }
 
 pub fn insert(task: &Knapsack, conn: &Connection) -> ApiResult<()> {
-    let task = Task::from_dto(task);
+    let task = Task::from_dto(task)?;
 
-    let mut conn = grab_lock(conn)?;
-
-    match retry(ExponentialBackoff::default(), || {
+    let rows_updated = retry_unreachable(conn, |conn| {
         diesel::insert_into(schema::tasks::table)
             .values(&task)
-            .execute(conn.deref_mut()).map_err(Error::transient)})
-    {
-        Ok(rows_updated) => {
-            if rows_updated != 1 {
-                eprintln!("Warning: db: Expected 1, was {}", rows_updated)
+            .execute(conn)
+    })?;
+    if rows_updated != 1 {
+        eprintln!("Warning: db: Expected 1, was {}", rows_updated)
+    }
+    Ok(())
+}
+
+/// Inserts the task unless the idempotency key is already in use, in which
+/// case the task it was used for is returned instead if it was submitted with
+/// the same request, and 409 otherwise
+pub fn insert_idempotent(task: &Knapsack, key: &str, request_hash: &str, conn: &Connection)
+                         -> ApiResult<Option<Knapsack>> {
+    use schema::tasks;
+    let task = Task {
+        idempotency_key: Some(key.to_string()),
+        request_hash: Some(request_hash.to_string()),
+        ..Task::from_dto(task)?
+    };
+
+    let mut locked = grab_lock(conn)?;
+
+    let rows_updated = map_db_result(
+        diesel::insert_into(tasks::table)
+            .values(&task)
+            .on_conflict(tasks::idempotency_key)
+            .do_nothing()
+            .execute(locked.deref_mut())
+    )?;
+    if rows_updated == 1 {
+        return Ok(None);
+    }
+
+    let (original, original_hash) = map_db_result(
+        tasks::table
+            .filter(tasks::idempotency_key.eq(key))
+            .select((tasks::id, tasks::request_hash))
+            .get_result::<(Uuid, Option<String>)>(locked.deref_mut())
+    )?;
+    idempotency::check_replay(original_hash.as_deref(), request_hash)?;
+    drop(locked);
+    get(original, conn).map(Some)
+}
+
+/// Inserts the tasks in a single transaction, so that either all or none of
+/// them are stored
+pub fn insert_all<'a>(knapsacks: impl IntoIterator<Item = &'a Knapsack>, conn: &Connection) -> ApiResult<()> {
+    let tasks = knapsacks.into_iter()
+        .map(Task::from_dto)
+        .collect::<ApiResult<Vec<_>>>()?;
+
+    retry_unreachable(conn, |conn| {
+        conn.transaction(|conn| {
+            // Postgres limits the number of parameters of a statement
+            for chunk in tasks.chunks(INSERT_CHUNK_SIZE) {
+                diesel::insert_into(schema::tasks::table)
+                    .values(chunk)
+                    .execute(conn)?;
             }
             Ok(())
-        },
-        Err(e) => match e {
-            Error::Permanent(err) => Err(to_http_status(err)),
-            Error::Transient { err, retry_after: _ } => Err(to_http_status(err)),
-        }
-    }
+        })
+    })
 }
 
-fn to_http_status(e: DbError) -> HttpStatus {
-    use diesel::result::Error::*;
-    eprintln!("{}", e);
-    HttpStatus::new(match e {
-        InvalidCString(_) => 400,
-        DatabaseError(_, _) => 500,
-        NotFound => 404,
-        QueryBuilderError(_) => todo!(),
-        DeserializationError(_) => todo!(),
-        SerializationError(_) => todo!(),
-        RollbackErrorOnCommit {
-            rollback_error: _re,
-            commit_error: _ce
-        } => todo!(),
-        RollbackTransaction => todo!(),
-        AlreadyInTransaction => todo!(),
-        NotInTransaction => todo!(),
-        BrokenTransactionManager => todo!(),
-        _ => todo!(),
+/// Runs the query, retrying it as long as the db can't be reached, while any
+/// other error fails right away. The lock is only held during each attempt,
+/// so that other requests can go ahead in between.
+fn retry_unreachable<T>(conn: &Connection, mut query: impl FnMut(&mut PgConnection) -> QueryResult<T>)
+                        -> ApiResult<T> {
+    use diesel::result::DatabaseErrorKind::{ClosedConnection, UnableToSendCommand};
+    retry(ExponentialBackoff::default(), || {
+        let mut locked = grab_lock(conn).map_err(Error::permanent)?;
+        query(locked.deref_mut()).map_err(|e| match e {
+            DbError::DatabaseError(ClosedConnection | UnableToSendCommand, _) => {
+                Error::transient(from_db_error(e))
+            },
+            e => Error::permanent(from_db_error(e)),
+        })
+    }).map_err(|e| match e {
+        Error::Permanent(err) | Error::Transient { err, retry_after: _ } => err,
     })
 }
 
-pub fn grab_lock(conn: &Connection) -> ApiResult<MutexGuard<PgConnection>> {
+/// Maps the error of a query to the error of the request. Errors which the
+/// request can't have caused are internal, except when the db is unavailable,
+/// as the request can then be retried.
+fn from_db_error(e: DbError) -> ApiError {
+    use diesel::result::DatabaseErrorKind::*;
+    use diesel::result::Error::*;
+    match e {
+        NotFound => ApiError::not_found("Not found"),
+        InvalidCString(_) => ApiError::new(Status::BadRequest, "invalid_string",
+                                           "Strings can't contain null characters"),
+        DatabaseError(kind, info) => match kind {
+            // e.g. the parent of a revision is gone
+            UniqueViolation | ForeignKeyViolation | RestrictViolation | ExclusionViolation => {
+                eprintln!("db: {}", info.message());
+                ApiError::new(Status::Conflict, "conflict", "The request conflicts with the stored tasks")
+            },
+            SerializationFailure => {
+                ApiError::new(Status::ServiceUnavailable, "database_busy", "The database is busy, try again")
+            },
+            UnableToSendCommand | ClosedConnection | ReadOnlyTransaction => {
+                eprintln!("db: {}", info.message());
+                ApiError::new(Status::ServiceUnavailable, "database_unavailable", "The database is unavailable")
+            },
+            NotNullViolation | CheckViolation => ApiError::internal(format!("db: {}", info.message())),
+            _ => ApiError::internal(format!("db: {}", info.message())),
+        },
+        QueryBuilderError(e) => ApiError::internal(format!("db: Could not build query: {}", e)),
+        DeserializationError(e) => ApiError::internal(format!("db: Could not read row: {}", e)),
+        SerializationError(e) => ApiError::internal(format!("db: Could not write value: {}", e)),
+        IntegerConversion(e) => ApiError::internal(format!("db: {}", e)),
+        RollbackErrorOnCommit { rollback_error, commit_error } => ApiError::internal(format!(
+            "db: Could not roll back ({}) after failed commit ({})", rollback_error, commit_error)),
+        RollbackTransaction | AlreadyInTransaction | NotInTransaction | BrokenTransactionManager => {
+            ApiError::internal(format!("db: {}", e))
+        },
+        _ => ApiError::internal(format!("db: {}", e)),
+    }
+}
+
+pub fn grab_lock(conn: &Connection) -> ApiResult<MutexGuard<'_, PgConnection>> {
     match conn.lock() {
         Ok(conn) => Ok(conn),
-        Err(e) => {
-            eprintln!("db: Could not grab lock: {}", e);
-            Err(Status::new(500))
-        }
+        Err(e) => Err(ApiError::internal(format!("db: Could not grab lock: {}", e))),
     }
 }
 
 pub fn get(task_id: Uuid, conn: &Connection) -> ApiResult<Knapsack> {
-    use schema::tasks;
     let mut conn = grab_lock(conn)?;
+    let knapsack = load(task_id, conn.deref_mut())?;
+
+    // A revision reports how its solution differs from its parent's
+    match &knapsack.revision {
+        Some(revision) if matches!(knapsack.status, TaskStatus::Completed) => {
+            let parent = load(revision.parent, conn.deref_mut())?;
+            Ok(knapsack.compare_to_parent(&parent))
+        },
+        _ => Ok(knapsack),
+    }
+}
+
+fn load(task_id: Uuid, conn: &mut PgConnection) -> ApiResult<Knapsack> {
+    use schema::tasks;
 
     let task = map_db_result(
         tasks::table
             .filter(tasks::id.eq(task_id))
             .select(Task::as_select())
+            .get_result(conn)
+            .optional()
+    )?.ok_or_else(|| no_such_task(task_id))?;
+
+    let knapsack = Knapsack::from_task(&task)?;
+
+    let solution = Solution::belonging_to(&task)
+        .filter(schema::solutions::rank.eq(0))
+        .select(Solution::as_select());
+    match task.status.as_str() {
+        "completed" => knapsack.set(map_db_result(solution.get_result(conn))?),
+        // A cancelled task only has a solution if it was running, and the
+        // best solution found until then was kept
+        "cancelled" => match map_db_result(solution.get_result(conn).optional())? {
+            Some(solution) => knapsack.set(solution),
+            None => Ok(knapsack),
+        },
+        _ => Ok(knapsack),
+    }
+}
+
+fn no_such_task(task_id: Uuid) -> ApiError {
+    ApiError::not_found(format!("There's no task {}", task_id))
+}
+
+/// Cancels a task which is submitted or started, and fails with 409 if it has
+/// already finished. A started task is stopped by the optimizer, which only
+/// stores the best solution found so far if `keep_best` is set.
+pub fn cancel(task_id: Uuid, keep_best: bool, conn: &Connection) -> ApiResult<Knapsack> {
+    use schema::tasks;
+    let mut conn = grab_lock(conn)?;
+
+    let rows_updated = map_db_result(conn.transaction(|conn| {
+        let status = tasks::table
+            .filter(tasks::id.eq(task_id))
+            .select(tasks::status)
+            .for_update()
+            .get_result::<String>(conn)
+            .optional()?;
+        let rows_updated = diesel::update(tasks::table)
+            .filter(tasks::id.eq(task_id))
+            .filter(tasks::status.eq_any(["submitted", "started"]))
+            .set((tasks::status.eq("cancelled"),
+                  tasks::ts_completed.eq(crate::current_time()),
+                  tasks::keep_best.eq(keep_best)))
+            .execute(conn)?;
+        // A started task which keeps its best solution is delivered by the
+        // optimizer, once the solution is stored
+        if rows_updated == 1 && !(keep_best && status.as_deref() == Some("started")) {
+            insert_delivery(task_id, conn)?;
+        }
+        if rows_updated == 1 {
+            notify_update(task_id, conn)?;
+        }
+        Ok(rows_updated)
+    }))?;
+
+    // Fails with 404 if there's no such task
+    let knapsack = load(task_id, conn.deref_mut())?;
+    if rows_updated == 1 {
+        Ok(knapsack)
+    } else {
+        Err(ApiError::new(Status::Conflict, "task_finished",
+                          format!("The task has already {}", knapsack.status)))
+    }
+}
+
+/// Notifies the change of the status of the task, once the transaction it's
+/// made in commits, see `updates`
+fn notify_update(task_id: Uuid, conn: &mut PgConnection) -> QueryResult<usize> {
+    use diesel::sql_types::Text;
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(updates::CHANNEL)
+        .bind::<Text, _>(task_id.to_string())
+        .execute(conn)
+}
+
+/// Enqueues the delivery of the finished task to its callback url, if it has
+/// one and it isn't enqueued already
+fn insert_delivery(task_id: Uuid, conn: &mut PgConnection) -> QueryResult<usize> {
+    use diesel::sql_types::{Integer, Text};
+    use schema::{deliveries, tasks};
+    diesel::insert_into(deliveries::table)
+        .values(tasks::table
+            .filter(tasks::id.eq(task_id))
+            .filter(tasks::callback_url.is_not_null())
+            .select((tasks::id,
+                     tasks::callback_url.assume_not_null(),
+                     "pending".into_sql::<Text>(),
+                     0.into_sql::<Integer>(),
+                     crate::current_time().into_sql::<Integer>())))
+        .into_columns((deliveries::task_id,
+                       deliveries::callback_url,
+                       deliveries::status,
+                       deliveries::attempts,
+                       deliveries::ts_next_attempt))
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+/// Claims up to `limit` of the pending deliveries which are due, leasing them
+/// for `lease` seconds, during which other instances of the api skip them
+pub fn claim_deliveries(limit: i64, lease: i32, conn: &Connection) -> ApiResult<Vec<Delivery>> {
+    use schema::deliveries;
+    let now = crate::current_time();
+    let mut conn = grab_lock(conn)?;
+
+    map_db_result(conn.transaction(|conn| {
+        let due: Vec<Uuid> = deliveries::table
+            .select(deliveries::task_id)
+            .filter(deliveries::status.eq("pending"))
+            .filter(deliveries::ts_next_attempt.le(now))
+            .order(deliveries::ts_next_attempt)
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load(conn)?;
+        diesel::update(deliveries::table)
+            .filter(deliveries::task_id.eq_any(due))
+            .set(deliveries::ts_next_attempt.eq(now + lease))
+            .returning(Delivery::as_returning())
+            .get_results(conn)
+    }))
+}
+
+/// Logs the attempt, along with the state of the delivery after it
+pub fn record_delivery_attempt(delivery: &Delivery, attempt: &DeliveryAttempt, conn: &Connection) -> ApiResult<()> {
+    use schema::delivery_attempts;
+    let mut conn = grab_lock(conn)?;
+
+    map_db_result(conn.transaction(|conn| {
+        diesel::insert_into(delivery_attempts::table)
+            .values(attempt)
+            .execute(conn)?;
+        diesel::update(delivery)
+            .set(delivery)
+            .execute(conn)
+    }))?;
+    Ok(())
+}
+
+pub fn get_delivery_log(task_id: Uuid, conn: &Connection) -> ApiResult<DeliveryLog> {
+    use schema::{deliveries, delivery_attempts};
+    let mut conn = grab_lock(conn)?;
+
+    // Fails with 404 until the task has finished
+    let delivery = map_db_result(
+        deliveries::table
+            .find(task_id)
+            .select(Delivery::as_select())
             .get_result(conn.deref_mut())
+            .optional()
+    )?.ok_or_else(|| ApiError::not_found(format!("There's no delivery of task {}", task_id)))?;
+    let attempts = map_db_result(
+        delivery_attempts::table
+            .filter(delivery_attempts::task_id.eq(task_id))
+            .order(delivery_attempts::attempt)
+            .select(DeliveryAttempt::as_select())
+            .load(conn.deref_mut())
     )?;
+    DeliveryLog::from_db(delivery, attempts)
+}
+
+/// All solutions of a task, which are only there once it's completed
+pub fn get_solutions(task_id: Uuid, conn: &Connection) -> ApiResult<Solutions> {
+    use schema::{solutions, tasks};
+    let mut conn = grab_lock(conn)?;
+
+    let task = map_db_result(
+        tasks::table
+            .filter(tasks::id.eq(task_id))
+            .select(Task::as_select())
+            .get_result(conn.deref_mut())
+            .optional()
+    )?.ok_or_else(|| no_such_task(task_id))?;
 
     let knapsack = Knapsack::from_task(&task)?;
 
-    if matches!(task.status.as_str(), "completed") {
-        let solution = map_db_result(
-            Solution::belonging_to(&task)
-                .select(Solution::as_select())
-                .get_result(conn.deref_mut())
-        )?;
+    let solutions = map_db_result(
+        Solution::belonging_to(&task)
+            .order(solutions::rank)
+            .select(Solution::as_select())
+            .load(conn.deref_mut())
+    )?;
+
+    Ok(Solutions {
+        task: knapsack.task,
+        solutions: solutions.into_iter()
+            .map(|solution| knapsack.solution(solution))
+            .collect::<ApiResult<_>>()?,
+        status: knapsack.status,
+    })
+}
+
+/// The sensitivity report of a completed task, which only exists if the task
+/// asked for it
+pub fn get_sensitivity_report(task_id: Uuid, conn: &Connection) -> ApiResult<SensitivityReport> {
+    let knapsack = get(task_id, conn)?;
+    let mut conn = grab_lock(conn)?;
+
+    let report = map_db_result(
+        sensitivity_reports::table
+            .filter(sensitivity_reports::task_id.eq(task_id))
+            .select(models::SensitivityReport::as_select())
+            .get_result(conn.deref_mut())
+            .optional()
+    )?.ok_or_else(|| ApiError::not_found(format!("Task {} has no sensitivity report", task_id)))?;
+
+    SensitivityReport::from_db(&knapsack, report)
+}
 
-        Ok(knapsack.set(solution)?)
+/// A page of the tasks matching the filter
+pub fn list(filter: &Filter, conn: &Connection) -> ApiResult<TaskList> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Integer, Nullable};
+    use schema::tasks;
+
+    // The tasks are filtered by the same query whether or not their problems
+    // are read, which is ordered and limited the same way too
+    let filtered = || {
+        let mut query = tasks::table.into_boxed();
+        if let Some(status) = &filter.status {
+            query = query.filter(tasks::status.eq(status.to_string()));
+        }
+        if let Some(owner) = &filter.owner {
+            query = query.filter(tasks::owner.eq(owner.clone()));
+        }
+        if !filter.tags.is_empty() {
+            let tags: Vec<Option<String>> = filter.tags.iter().cloned().map(Some).collect();
+            query = query.filter(tasks::tags.contains(tags));
+        }
+        if let Some(after) = filter.submitted_after {
+            query = query.filter(tasks::ts_submitted.ge(after));
+        }
+        if let Some(before) = filter.submitted_before {
+            query = query.filter(tasks::ts_submitted.lt(before));
+        }
+        // Tasks after the cursor, in the order of the list
+        query = match (&filter.cursor, filter.ascending) {
+            (Some(cursor), true) => query.filter(
+                tasks::ts_submitted.gt(cursor.submitted)
+                    .or(tasks::ts_submitted.eq(cursor.submitted).and(tasks::id.gt(cursor.task)))),
+            (Some(cursor), false) => query.filter(
+                tasks::ts_submitted.lt(cursor.submitted)
+                    .or(tasks::ts_submitted.eq(cursor.submitted).and(tasks::id.lt(cursor.task)))),
+            (None, _) => query,
+        };
+        query = if filter.ascending {
+            query.order((tasks::ts_submitted.asc(), tasks::id.asc()))
+        } else {
+            query.order((tasks::ts_submitted.desc(), tasks::id.desc()))
+        };
+        // One more task than asked for tells whether there's a next page
+        query.limit(i64::from(filter.limit) + 1)
+    };
+
+    let mut conn = grab_lock(conn)?;
+    let mut summaries = if filter.full {
+        map_db_result(filtered().select(Task::as_select()).load(conn.deref_mut()))?
+            .iter()
+            .map(|task| TaskSummary::from_knapsack(Knapsack::from_task(task)?))
+            .collect::<ApiResult<Vec<_>>>()?
     } else {
-        Ok(knapsack)
-    }
+        let items = sql::<Nullable<Integer>>("array_length(problem_weights, 1)");
+        map_db_result(filtered()
+            .select((models::TaskSummary::as_select(), items))
+            .load(conn.deref_mut()))?
+            .into_iter()
+            .map(|(task, items)| TaskSummary::from_db(task, items))
+            .collect::<ApiResult<Vec<_>>>()?
+    };
+
+    let next_cursor = if summaries.len() > filter.limit as usize {
+        summaries.truncate(filter.limit as usize);
+        summaries.last().map(|last| Cursor {
+            submitted: last.timestamps.submitted,
+            task: last.task,
+        }.to_string())
+    } else {
+        None
+    };
+    Ok(TaskList { tasks: summaries, next_cursor })
 }
 
 fn map_db_result<T>(res: QueryResult<T>) -> ApiResult<T> {
     match res {
         Ok(task) => Ok(task),
-        Err(e) => Err(to_http_status(e))
+        Err(e) => Err(from_db_error(e))
     }
 }
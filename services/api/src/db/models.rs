@@ -1,7 +1,8 @@
 use diesel::prelude::*;
 use uuid::Uuid;
 
-use crate::{ApiResult, HttpStatus, Knapsack};
+use crate::{ApiError, ApiResult, Knapsack};
+use crate::dto::Algorithm;
 
 #[derive(Queryable, Selectable, Insertable, Identifiable, Debug)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -12,49 +13,201 @@ pub struct Task {
     pub ts_submitted: i32,
     pub ts_started: Option<i32>,
     pub ts_completed: Option<i32>,
-    pub problem_capacity: i32,
-    pub problem_weights: Vec<Option<i32>>,
-    pub problem_values: Vec<Option<i32>>,
+    pub problem_capacity: i64,
+    pub problem_weights: Vec<Option<i64>>,
+    pub problem_values: Vec<Option<i64>>,
+    pub problem_weight_scale: i32,
+    pub problem_value_scale: i32,
+    pub algorithm: String,
+    pub epsilon: Option<f64>,
+    pub initial_solution: Option<Vec<Option<i32>>>,
+    pub sensitivity: bool,
+    pub alternatives: Option<i32>,
+    pub optimal_only: bool,
+    pub tie_break: Option<String>,
+    // set if the task is a revision of another task
+    pub parent_id: Option<Uuid>,
+    pub removed_items: Option<Vec<Option<i32>>>,
+    // upper bound on the optimal value, known from the parent task
+    pub inherited_bound: Option<i64>,
+    // maximum deviations of the weights, of which at most gamma occur at once
+    pub problem_deviations: Option<Vec<Option<i64>>>,
+    pub gamma: Option<i32>,
+    // limits on the number of packed items, in total and per category
+    pub max_items: Option<i32>,
+    pub categories: Option<Vec<Option<String>>>,
+    pub category_names: Option<Vec<Option<String>>>,
+    pub category_limits: Option<Vec<Option<i32>>>,
+    // linear constraints on the packed items, with the coefficients of each
+    // constraint one after the other
+    pub constraint_coefficients: Option<Vec<Option<f64>>>,
+    pub constraint_bounds: Option<Vec<Option<f64>>>,
+    // only set once the task is cancelled, whether the best solution found
+    // until then is kept
+    pub keep_best: Option<bool>,
+    // who submitted the task, and labels to find it by
+    pub owner: Option<String>,
+    pub tags: Option<Vec<Option<String>>>,
+    // only set if the task was submitted with an idempotency key, along with
+    // the hash of the request
+    pub idempotency_key: Option<String>,
+    pub request_hash: Option<String>,
+    // where the knapsack is posted once the task has finished
+    pub callback_url: Option<String>,
 }
 
 impl Task {
-    pub fn from_dto(task: &Knapsack) -> Self {
-        Task {
+    /// The problem must be valid, so that it can be scaled to integers
+    pub fn from_dto(task: &Knapsack) -> ApiResult<Self> {
+        let problem = task.problem.scaled()
+            .ok_or_else(|| ApiError::internal(format!("Problem of task {} could not be scaled", task.task)))?;
+        Ok(Task {
             id: task.task,
             status: format!("{}", task.status),
             ts_submitted: task.timestamps.submitted,
             ts_started: task.timestamps.started,
             ts_completed: task.timestamps.completed,
-            problem_capacity: task.problem.capacity as i32,
-            problem_weights: map_arr(&task.problem.weights),
-            problem_values: map_arr(&task.problem.values),
-        }
+            problem_capacity: problem.capacity as i64,
+            problem_weights: map_arr(&problem.weights),
+            problem_values: map_arr(&problem.values),
+            problem_weight_scale: problem.precision.weights as i32,
+            problem_value_scale: problem.precision.values as i32,
+            algorithm: task.problem.algorithm
+                .unwrap_or(Algorithm::Genetic).to_string(),
+            epsilon: task.problem.epsilon,
+            initial_solution: task.problem.initial_solution.as_deref()
+                .map(|items| items.iter().map(|i| Some(*i as i32)).collect()),
+            sensitivity: task.problem.sensitivity,
+            alternatives: task.problem.alternatives.map(|a| a.count as i32),
+            optimal_only: task.problem.alternatives.is_some_and(|a| a.optimal_only),
+            tie_break: task.problem.tie_break.map(|t| t.to_string()),
+            parent_id: task.revision.as_ref().map(|r| r.parent),
+            removed_items: task.revision.as_ref()
+                .map(|r| r.removed_items.iter().map(|i| Some(*i as i32)).collect()),
+            inherited_bound: task.revision.as_ref()
+                .and_then(|r| r.inherited_bound)
+                .map(|b| b as i64),
+            problem_deviations: task.problem.gamma.map(|_| map_arr(&problem.deviations)),
+            gamma: task.problem.gamma.map(|g| g as i32),
+            max_items: task.problem.max_items.map(|m| m as i32),
+            categories: task.problem.categories.clone(),
+            category_names: task.problem.category_limits.as_ref()
+                .map(|limits| limits.keys().cloned().map(Some).collect()),
+            category_limits: task.problem.category_limits.as_ref()
+                .map(|limits| limits.values().map(|l| Some(*l as i32)).collect()),
+            constraint_coefficients: task.problem.constraints.as_ref()
+                .map(|constraints| constraints.iter()
+                     .flat_map(|c| c.coefficients.iter().copied().map(Some))
+                     .collect()),
+            constraint_bounds: task.problem.constraints.as_ref()
+                .map(|constraints| constraints.iter().map(|c| Some(c.bound)).collect()),
+            keep_best: None,
+            owner: task.problem.owner.clone(),
+            tags: task.problem.tags.as_ref()
+                .map(|tags| tags.iter().cloned().map(Some).collect()),
+            idempotency_key: None,
+            request_hash: None,
+            callback_url: task.problem.callback_url.clone(),
+        })
     }
 }
 
+/// The columns of a task which are listed without its problem
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = crate::db::schema::tasks)]
+pub struct TaskSummary {
+    pub id: Uuid,
+    pub status: String,
+    pub ts_submitted: i32,
+    pub ts_started: Option<i32>,
+    pub ts_completed: Option<i32>,
+    pub algorithm: String,
+    pub parent_id: Option<Uuid>,
+    pub owner: Option<String>,
+    pub tags: Option<Vec<Option<String>>>,
+}
+
 #[derive(Queryable, Selectable, Insertable, Identifiable, Associations, Debug)]
 #[diesel(belongs_to(Task))]
 #[diesel(table_name = crate::db::schema::solutions)]
 pub struct Solution {
     pub id: Uuid,
     pub packed_items: Vec<Option<i32>>,
-    pub total_value: i32,
+    pub total_value: i64,
+    pub task_id: Uuid,
+    pub upper_bound: Option<i64>,
+    // 0 for the best solution of the task, 1 for the second best, ...
+    pub rank: i32,
+}
+
+#[derive(Queryable, Selectable, Insertable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Task))]
+#[diesel(primary_key(task_id))]
+#[diesel(table_name = crate::db::schema::sensitivity_reports)]
+pub struct SensitivityReport {
     pub task_id: Uuid,
+    pub optimal_value: i64,
+    pub capacity_price: f64,
+    pub reduced_costs: Vec<Option<f64>>,
+    pub value_thresholds: Vec<Option<i64>>,
+    pub extra_capacities: Vec<Option<i64>>,
+    pub capacity_increments: Vec<Option<i64>>,
+    pub capacity_values: Vec<Option<i64>>,
 }
 
-/// PostgreSQL rows of type `integer[] NOT NULL` are allowed to have null entries
+/// The delivery of a finished task to its callback url, see `webhook`
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Task))]
+#[diesel(primary_key(task_id))]
+#[diesel(table_name = crate::db::schema::deliveries)]
+pub struct Delivery {
+    pub task_id: Uuid,
+    pub callback_url: String,
+    pub status: String,
+    pub attempts: i32,
+    // while the delivery is pending, and until then it's leased to whoever
+    // is attempting it
+    pub ts_next_attempt: i32,
+    pub ts_delivered: Option<i32>,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = crate::db::schema::delivery_attempts)]
+pub struct DeliveryAttempt {
+    pub task_id: Uuid,
+    pub attempt: i32,
+    pub ts_attempted: i32,
+    // only set if there was a response, and otherwise the error is
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// PostgreSQL rows of type `bigint[] NOT NULL` are allowed to have null entries
 /// in the array. I don't think it's possible to specify that all entries are
 /// non-null...
-pub fn map_arr(arr: &[u32]) -> Vec<Option<i32>> {
-    arr.iter().map(|i| Some(*i as i32)).collect()
+pub fn map_arr(arr: &[u64]) -> Vec<Option<i64>> {
+    arr.iter().map(|i| Some(*i as i64)).collect()
 }
 
-pub fn map_arr_inv(arr: &[Option<i32>]) -> ApiResult<Vec<u32>> {
-    if !arr.iter().all(|i| i.is_some()) {
-        // This is an application error, as Rocket ensures all inputted vectors
-        // for weights and values contain non-null entries
-        Err(HttpStatus::new(500))
-    } else {
-        Ok(arr.iter().map(|i| i.unwrap() as u32).collect())
-    }
+pub fn map_arr_inv<T, U>(arr: &[Option<T>]) -> ApiResult<Vec<U>>
+where
+    T: Copy,
+    U: TryFrom<T>,
+{
+    // A null entry is an application error, as Rocket ensures all inputted
+    // vectors for weights and values contain non-null entries
+    arr.iter()
+        .map(|i| i.ok_or_else(null_entry).and_then(map_int_inv))
+        .collect()
+}
+
+pub fn null_entry() -> ApiError {
+    ApiError::internal("db: Array has a null entry")
+}
+
+/// Converts an integer read from the db back to its unsigned counterpart,
+/// which only fails if the db contains negative numbers we didn't put there
+pub fn map_int_inv<T, U: TryFrom<T>>(i: T) -> ApiResult<U> {
+    U::try_from(i).map_err(|_| ApiError::internal("db: Number is out of range"))
 }
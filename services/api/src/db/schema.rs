@@ -1,11 +1,49 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    deliveries (task_id) {
+        task_id -> Uuid,
+        #[max_length = 2048]
+        callback_url -> Varchar,
+        #[max_length = 20]
+        status -> Varchar,
+        attempts -> Int4,
+        ts_next_attempt -> Int4,
+        ts_delivered -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    delivery_attempts (task_id, attempt) {
+        task_id -> Uuid,
+        attempt -> Int4,
+        ts_attempted -> Int4,
+        response_status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    sensitivity_reports (task_id) {
+        task_id -> Uuid,
+        optimal_value -> Int8,
+        capacity_price -> Float8,
+        reduced_costs -> Array<Nullable<Float8>>,
+        value_thresholds -> Array<Nullable<Int8>>,
+        extra_capacities -> Array<Nullable<Int8>>,
+        capacity_increments -> Array<Nullable<Int8>>,
+        capacity_values -> Array<Nullable<Int8>>,
+    }
+}
+
 diesel::table! {
     solutions (id) {
         id -> Uuid,
         packed_items -> Array<Nullable<Int4>>,
-        total_value -> Int4,
+        total_value -> Int8,
         task_id -> Uuid,
+        upper_bound -> Nullable<Int8>,
+        rank -> Int4,
     }
 }
 
@@ -17,15 +55,53 @@ diesel::table! {
         ts_submitted -> Int4,
         ts_started -> Nullable<Int4>,
         ts_completed -> Nullable<Int4>,
-        problem_capacity -> Int4,
-        problem_weights -> Array<Nullable<Int4>>,
-        problem_values -> Array<Nullable<Int4>>,
+        problem_capacity -> Int8,
+        problem_weights -> Array<Nullable<Int8>>,
+        problem_values -> Array<Nullable<Int8>>,
+        problem_weight_scale -> Int4,
+        problem_value_scale -> Int4,
+        #[max_length = 20]
+        algorithm -> Varchar,
+        epsilon -> Nullable<Float8>,
+        initial_solution -> Nullable<Array<Nullable<Int4>>>,
+        sensitivity -> Bool,
+        alternatives -> Nullable<Int4>,
+        optimal_only -> Bool,
+        #[max_length = 20]
+        tie_break -> Nullable<Varchar>,
+        parent_id -> Nullable<Uuid>,
+        removed_items -> Nullable<Array<Nullable<Int4>>>,
+        inherited_bound -> Nullable<Int8>,
+        problem_deviations -> Nullable<Array<Nullable<Int8>>>,
+        gamma -> Nullable<Int4>,
+        max_items -> Nullable<Int4>,
+        categories -> Nullable<Array<Nullable<Text>>>,
+        category_names -> Nullable<Array<Nullable<Text>>>,
+        category_limits -> Nullable<Array<Nullable<Int4>>>,
+        constraint_coefficients -> Nullable<Array<Nullable<Float8>>>,
+        constraint_bounds -> Nullable<Array<Nullable<Float8>>>,
+        keep_best -> Nullable<Bool>,
+        #[max_length = 64]
+        owner -> Nullable<Varchar>,
+        tags -> Nullable<Array<Nullable<Text>>>,
+        #[max_length = 255]
+        idempotency_key -> Nullable<Varchar>,
+        #[max_length = 64]
+        request_hash -> Nullable<Bpchar>,
+        #[max_length = 2048]
+        callback_url -> Nullable<Varchar>,
     }
 }
 
+diesel::joinable!(deliveries -> tasks (task_id));
+diesel::joinable!(delivery_attempts -> deliveries (task_id));
+diesel::joinable!(sensitivity_reports -> tasks (task_id));
 diesel::joinable!(solutions -> tasks (task_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    deliveries,
+    delivery_attempts,
+    sensitivity_reports,
     solutions,
     tasks,
 );
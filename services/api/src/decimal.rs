@@ -0,0 +1,197 @@
+//! Non-negative decimal numbers, such as weights in kg with grams as decimals
+//! or prices in euros with cents.
+//!
+//! The solvers only work on integers, so decimals are scaled by a power of ten
+//! before they are stored, and scaled back when they are returned to the
+//! caller. A decimal is represented exactly as `units / 10^scale`, e.g. 12.35
+//! as 1235 units with a scale of 2.
+
+use std::fmt;
+
+use serde::de::{self, Deserializer, Error as _};
+use serde::ser::{Error as _, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use utoipa::openapi::schema::{ObjectBuilder, OneOfBuilder, Schema, Type};
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
+
+/// Maximum number of decimal places, so that `10^scale` fits into 64 bits
+pub const MAX_SCALE: u32 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decimal {
+    pub units: u128,
+    pub scale: u32,
+}
+
+impl Decimal {
+    pub fn from_scaled(units: u128, scale: u32) -> Self {
+        Self { units, scale }
+    }
+
+    /// The integer this number corresponds to when scaled by `10^scale`, or
+    /// `None` if it has more decimal places than that or doesn't fit in 128 bits
+    pub fn rescale(&self, scale: u32) -> Option<u128> {
+        let exponent = scale.checked_sub(self.scale)?;
+        10u128.checked_pow(exponent)?.checked_mul(self.units)
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if integer.is_empty() || !all_digits(integer) || !all_digits(fraction) {
+            return None;
+        }
+        let scale = u32::try_from(fraction.len()).ok().filter(|s| *s <= MAX_SCALE)?;
+        let units = format!("{}{}", integer, fraction).parse().ok()?;
+        Some(Self { units, scale })
+    }
+
+    /// Parses a JSON number, which unlike `parse` may have an exponent, e.g.
+    /// `1.25e2` is 125
+    fn parse_number(s: &str) -> Option<Self> {
+        let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => {
+                (mantissa, exponent.strip_prefix('+').unwrap_or(exponent).parse::<i64>().ok()?)
+            },
+            None => (s, 0),
+        };
+        let Self { units, scale } = Self::parse(mantissa)?;
+        match u32::try_from(i64::from(scale).checked_sub(exponent)?) {
+            Ok(scale) => Some(Self { units, scale }).filter(|_| scale <= MAX_SCALE),
+            Err(_) => {
+                let exponent = u32::try_from(exponent - i64::from(scale)).ok()?;
+                let units = 10u128.checked_pow(exponent)?.checked_mul(units)?;
+                Some(Self { units, scale: 0 })
+            },
+        }
+    }
+}
+
+impl From<u64> for Decimal {
+    fn from(units: u64) -> Self {
+        Self { units: u128::from(units), scale: 0 }
+    }
+}
+
+/// Formats the number without trailing zeros in the decimals, so that e.g.
+/// 1230 units with a scale of 2 becomes `12.3`
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = format!("{:0>width$}", self.units, width = self.scale as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{}", integer)
+        } else {
+            write!(f, "{}.{}", integer, fraction)
+        }
+    }
+}
+
+/// Serializes the number as a JSON number with exactly the digits given by
+/// `Display`, instead of going through a lossy float
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawValue::from_string(self.to_string())
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+/// Parses the JSON text the caller wrote, so that no digits are lost however
+/// many there are, rather than going through a float
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        let text = raw.get();
+        let decimal = if text.starts_with('"') {
+            let s: String = serde_json::from_str(text).map_err(D::Error::custom)?;
+            Decimal::parse(&s)
+        } else {
+            Decimal::parse_number(text)
+        };
+        decimal.ok_or_else(|| D::Error::invalid_value(de::Unexpected::Other(text), &Expected))
+    }
+}
+
+/// Documents both forms which are accepted, while the number is always
+/// returned as a JSON number
+impl PartialSchema for Decimal {
+    fn schema() -> RefOr<Schema> {
+        OneOfBuilder::new()
+            .item(ObjectBuilder::new().schema_type(Type::Number).minimum(Some(0)))
+            .item(ObjectBuilder::new().schema_type(Type::String).pattern(Some(r"^[0-9]+(\.[0-9]+)?$")))
+            .description(Some(format!("A non-negative number with at most {} decimals", MAX_SCALE)))
+            .into()
+    }
+}
+
+impl ToSchema for Decimal {}
+
+struct Expected;
+
+impl de::Expected for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a non-negative number with at most {} decimals, \
+                   or a string containing one", MAX_SCALE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(units: u128, scale: u32) -> Decimal {
+        Decimal::from_scaled(units, scale)
+    }
+
+    #[test]
+    fn parses_the_digits_given() {
+        assert_eq!(Decimal::parse("12.35"), Some(decimal(1235, 2)));
+        assert_eq!(Decimal::parse("12.30"), Some(decimal(1230, 2)));
+        assert_eq!(Decimal::parse("007"), Some(decimal(7, 0)));
+        assert_eq!(Decimal::parse("0.000000000000000001"), Some(decimal(1, 18)));
+        for invalid in ["", ".5", "-1", "+1", "1e3", "1.2.3", " 1", "0.0000000000000000001"] {
+            assert_eq!(Decimal::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn rescales_without_losing_decimals() {
+        assert_eq!(decimal(1235, 2).rescale(2), Some(1235));
+        assert_eq!(decimal(1235, 2).rescale(5), Some(1235000));
+        assert_eq!(decimal(1235, 2).rescale(1), None);
+        assert_eq!(decimal(u128::MAX, 0).rescale(1), None);
+        assert_eq!(decimal(1, 0).rescale(39), None);
+    }
+
+    #[test]
+    fn deserializes_numbers_exactly() {
+        let parse = |json: &str| serde_json::from_str::<Decimal>(json).ok();
+        assert_eq!(parse("12.35"), Some(decimal(1235, 2)));
+        assert_eq!(parse("\"12.35\""), Some(decimal(1235, 2)));
+        assert_eq!(parse("18446744073709551616"), Some(decimal(1 << 64, 0)));
+        // More digits than a float holds
+        assert_eq!(parse("12345678901234567890.123456789"),
+                   Some(decimal(12345678901234567890123456789, 9)));
+        assert_eq!(parse("9007199254740993.0"), Some(decimal(90071992547409930, 1)));
+        assert_eq!(parse("1.25e2"), Some(decimal(125, 0)));
+        assert_eq!(parse("125E-2"), Some(decimal(125, 2)));
+        assert_eq!(parse("1e+3"), Some(decimal(1000, 0)));
+        for invalid in ["-1", "-0.5", "1e-19", "1e39", "1e-9223372036854775808", "true", "null", "[1]",
+                        "\"-1\"", "\"1e3\""] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn serializes_without_trailing_zeros() {
+        let json = |d: Decimal| serde_json::to_string(&d).unwrap();
+        assert_eq!(json(decimal(1230, 2)), "12.3");
+        assert_eq!(json(decimal(1200, 2)), "12");
+        assert_eq!(json(decimal(5, 3)), "0.005");
+        assert_eq!(json(decimal(12345678901234567890123456789, 9)), "12345678901234567890.123456789");
+    }
+}
@@ -1,21 +1,339 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::db::models::{Task as DbTask, Solution as DbSolution};
-use crate::{ApiResult, HttpStatus};
-use crate::db::models::map_arr_inv;
+use crate::db::models::SensitivityReport as DbSensitivityReport;
+use crate::{ApiError, ApiResult};
+use crate::db::models::{map_arr_inv, map_int_inv, null_entry};
+use crate::decimal::Decimal;
+use crate::revise::{Changes, Revision};
+use crate::validation::{self, Limits};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Maximum number of solutions a task can ask for
+pub const MAX_ALTERNATIVES: u32 = 100;
+
+/// Maximum number of tags of a task, and length of a tag or owner
+pub const MAX_TAGS: usize = 20;
+pub const MAX_LABEL_LENGTH: usize = 64;
+
+include!("../../shared/constraint_tolerance.rs");
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Problem {
-    pub capacity: u32,
-    pub weights: Vec<u32>,
-    pub values: Vec<u32>,
+    pub capacity: Decimal,
+    pub weights: Vec<Decimal>,
+    pub values: Vec<Decimal>,
+    // Defaults to the largest number of decimals given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<Precision>,
+    // Defaults to `fptas` if `epsilon` is given, `branch_and_bound` if
+    // `alternatives` or `tie_break` is, `milp` if `constraints` is, and
+    // `genetic` otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<Algorithm>,
+    // Approximation ratio for the `fptas` algorithm, in the range (0, 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epsilon: Option<f64>,
+    // Indices of the packed items of a known solution to start from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_solution: Option<Vec<u32>>,
+    // Whether to analyse the sensitivity of the solution, see `SensitivityReport`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitivity: bool,
+    // Whether to find more solutions than the best one, see `Alternatives`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alternatives: Option<Alternatives>,
+    // Which of several packings of the same value to prefer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tie_break: Option<TieBreak>,
+    // Maximum deviations of the weights, in the same units, of which at most
+    // `gamma` occur at once. Packings must fit in the worst case.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deviations: Option<Vec<Decimal>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<u32>,
+    // Maximum number of packed items
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<u32>,
+    // Category of each item, if any, and the maximum number of packed items
+    // of some of the categories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub categories: Option<Vec<Option<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category_limits: Option<BTreeMap<String, u32>>,
+    // Linear side constraints on the packed items, see `Constraint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraints: Option<Vec<Constraint>>,
+    // Who submitted the problem, and labels to find it by, see `GET /knapsack`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    // An http(s) url the knapsack is posted to once the task has completed,
+    // failed or been cancelled, see `webhook`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+}
+
+/// The constraint `sum coefficients[i] * x[i] <= bound`, where `x[i]` is 1 if
+/// item `i` is packed and 0 otherwise. Only the `milp` algorithm supports it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Constraint {
+    pub coefficients: Vec<f64>,
+    pub bound: f64,
+}
+
+impl Constraint {
+    /// Whether the packed items satisfy the constraint, up to the tolerance
+    pub fn holds(&self, packed_items: &[u32]) -> bool {
+        let sum: f64 = packed_items.iter()
+            .map(|i| self.coefficients[*i as usize])
+            .sum();
+        sum <= self.bound + CONSTRAINT_TOLERANCE * self.bound.abs().max(1.0)
+    }
+}
+
+/// Asks for the `count` best distinct solutions, or if `optimal_only` is set,
+/// for up to `count` different solutions which are all optimal
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub struct Alternatives {
+    pub count: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optimal_only: bool,
+}
+
+/// The number of decimals of the capacity and weights, and of the values,
+/// which they are scaled by to get the integers used by the solvers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub struct Precision {
+    pub weights: u32,
+    pub values: u32,
+}
+
+/// The problem as seen by the solvers, with all numbers scaled to integers
+pub struct ScaledProblem {
+    pub capacity: u64,
+    pub weights: Vec<u64>,
+    pub values: Vec<u64>,
+    // all zero unless the problem is robust
+    pub deviations: Vec<u64>,
+    pub gamma: Option<u32>,
+    pub max_items: Option<u32>,
+    pub categories: Option<Vec<Option<String>>>,
+    pub category_limits: BTreeMap<String, u32>,
+    pub constraints: Vec<Constraint>,
+    pub precision: Precision,
+}
+
+impl ScaledProblem {
+    /// The weight of the packed items in the worst case, where the `gamma`
+    /// largest deviations among them occur. The items must be in range and
+    /// distinct.
+    pub fn worst_case_weight(&self, packed_items: &[u32]) -> u128 {
+        let mut deviations: Vec<u64> = packed_items.iter()
+            .map(|i| self.deviations[*i as usize])
+            .collect();
+        deviations.sort_by(|a, b| b.cmp(a));
+        let gamma = self.gamma.unwrap_or(0) as usize;
+        packed_items.iter()
+            .map(|i| u128::from(self.weights[*i as usize]))
+            .chain(deviations.into_iter().take(gamma).map(u128::from))
+            .sum()
+    }
+
+    /// Describes how the packed items exceed the limits on their number, in
+    /// total and per category. The items must be in range and distinct.
+    pub fn limit_violations(&self, packed_items: &[u32]) -> Vec<String> {
+        let mut violations = vec![];
+        if let Some(max_items) = self.max_items {
+            if packed_items.len() > max_items as usize {
+                violations.push(format!("{} items exceed the limit of {}",
+                                        packed_items.len(), max_items));
+            }
+        }
+        if let Some(categories) = &self.categories {
+            for (category, limit) in &self.category_limits {
+                let count = packed_items.iter()
+                    .filter(|i| categories[**i as usize].as_ref() == Some(category))
+                    .count();
+                if count > *limit as usize {
+                    violations.push(format!("{} items of category {} exceed its limit of {}",
+                                            count, category, limit));
+                }
+            }
+        }
+        violations
+    }
+
+    /// Describes which linear constraints the packed items violate. The items
+    /// must be in range and distinct.
+    pub fn constraint_violations(&self, packed_items: &[u32]) -> Vec<String> {
+        self.constraints.iter()
+            .enumerate()
+            .filter(|(_, constraint)| !constraint.holds(packed_items))
+            .map(|(j, constraint)| format!("constraint {} exceeds its bound of {}",
+                                           j, constraint.bound))
+            .collect()
+    }
+}
+
+impl Problem {
+    /// A problem with the given items and no options
+    pub fn new(capacity: Decimal, weights: Vec<Decimal>, values: Vec<Decimal>) -> Self {
+        Self {
+            capacity,
+            weights,
+            values,
+            precision: None,
+            algorithm: None,
+            epsilon: None,
+            initial_solution: None,
+            sensitivity: false,
+            alternatives: None,
+            tie_break: None,
+            deviations: None,
+            gamma: None,
+            max_items: None,
+            categories: None,
+            category_limits: None,
+            constraints: None,
+            owner: None,
+            tags: None,
+            callback_url: None,
+        }
+    }
+
+    pub fn precision(&self) -> Precision {
+        let max_scale = |numbers: &mut dyn Iterator<Item = &Decimal>| {
+            numbers.map(|n| n.scale).max().unwrap_or(0)
+        };
+        let deviations = self.deviations.iter().flatten();
+        self.precision.unwrap_or(Precision {
+            weights: max_scale(&mut std::iter::once(&self.capacity)
+                               .chain(&self.weights)
+                               .chain(deviations)),
+            values: max_scale(&mut self.values.iter()),
+        })
+    }
+
+    /// Scales all numbers to integers, failing if any of them has more
+    /// decimals than the precision allows, or if they can't be represented
+    /// by i64, which is what the ORM expects.
+    pub fn scaled(&self) -> Option<ScaledProblem> {
+        let precision = self.precision();
+        let scale = |n: &Decimal, scale: u32| {
+            n.rescale(scale)
+                .and_then(|n| i64::try_from(n).ok())
+                .map(|n| n as u64)
+        };
+        Some(ScaledProblem {
+            capacity: scale(&self.capacity, precision.weights)?,
+            weights: self.weights.iter()
+                .map(|w| scale(w, precision.weights))
+                .collect::<Option<_>>()?,
+            values: self.values.iter()
+                .map(|v| scale(v, precision.values))
+                .collect::<Option<_>>()?,
+            deviations: match &self.deviations {
+                Some(deviations) => deviations.iter()
+                    .map(|d| scale(d, precision.weights))
+                    .collect::<Option<_>>()?,
+                None => vec![0; self.weights.len()],
+            },
+            gamma: self.gamma,
+            max_items: self.max_items,
+            categories: self.categories.clone(),
+            category_limits: self.category_limits.clone().unwrap_or_default(),
+            constraints: self.constraints.clone().unwrap_or_default(),
+            precision,
+        })
+    }
+
+    /// Fails with all violations of the problem, see `validation`
+    pub fn validate(&self, limits: &Limits) -> ApiResult<()> {
+        let violations = validation::violations(self, limits);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::invalid_fields(violations))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    Genetic,
+    Fptas,
+    BranchAndBound,
+    Milp,
+}
+
+impl Algorithm {
+    pub fn from_str(algorithm: &str) -> ApiResult<Self> {
+        use Algorithm::*;
+        match algorithm {
+            "genetic" => Ok(Genetic),
+            "fptas" => Ok(Fptas),
+            "branch_and_bound" => Ok(BranchAndBound),
+            "milp" => Ok(Milp),
+            _ => Err(ApiError::internal(format!("Unknown algorithm {}", algorithm)))
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Algorithm::Genetic => write!(f, "genetic"),
+            Algorithm::Fptas => write!(f, "fptas"),
+            Algorithm::BranchAndBound => write!(f, "branch_and_bound"),
+            Algorithm::Milp => write!(f, "milp"),
+        }
+    }
+}
+
+/// Secondary objective deciding between packings of the same value. Packings
+/// which are still tied are ordered lexicographically.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    // least total weight
+    LeastWeight,
+    // fewest packed items
+    FewestItems,
+    // lexicographically smallest indices of the packed items
+    Lexicographic,
+}
+
+impl TieBreak {
+    fn from_str(tie_break: &str) -> ApiResult<Self> {
+        use TieBreak::*;
+        match tie_break {
+            "least_weight" => Ok(LeastWeight),
+            "fewest_items" => Ok(FewestItems),
+            "lexicographic" => Ok(Lexicographic),
+            _ => Err(ApiError::internal(format!("Unknown tie break {}", tie_break)))
+        }
+    }
+}
+
+impl Display for TieBreak {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TieBreak::LeastWeight => write!(f, "least_weight"),
+            TieBreak::FewestItems => write!(f, "fewest_items"),
+            TieBreak::Lexicographic => write!(f, "lexicographic"),
+        }
+    }
 }
 
 /// This type is only used to properly serialize the problem object
 /// when it appears inside the Knapsack struct.
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ProblemBody {
     Problem(Problem),
@@ -34,35 +352,39 @@ impl ProblemBody {
         }
     }
 
-    /// Validate that none of the inputs are too large, so that primitive
-    /// type casting to i32, which is what the ORM expects, is safe.
-    pub fn validate(&self) -> ApiResult<()> {
-        use crate::HttpStatus;
-        let problem = self.to_problem();
-        let assert_all_representable_by_i32 = |vec: &[u32]| -> ApiResult<()> {
-            if vec.iter().any(|v| i32::try_from(*v).is_err()) {
-                Err(HttpStatus::new(400))
-            } else {
-                Ok(())
-            }
-        };
-        assert_all_representable_by_i32(&problem.weights)
-            .and_then(|_| assert_all_representable_by_i32(&problem.values))
+    pub fn validate(&self, limits: &Limits) -> ApiResult<()> {
+        self.to_problem().validate(limits).map_err(|e| e.at("/problem"))
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Knapsack {
     pub task: Uuid,
     pub status: Status,
     pub timestamps: Timestamps,
     pub problem: Problem,
+    // only set if the task is a revision of another task
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<Revision>,
     pub solution: MyOption<Solution>,
 }
 
 
 impl Knapsack {
     pub fn new(problem: Problem) -> Self {
+        let algorithm = problem.algorithm.unwrap_or(
+            if problem.epsilon.is_some() {
+                Algorithm::Fptas
+            } else if problem.alternatives.is_some() || problem.tie_break.is_some() {
+                Algorithm::BranchAndBound
+            } else if problem.constraints.is_some() {
+                Algorithm::Milp
+            } else {
+                Algorithm::Genetic
+            });
+        // Report the precision in use whenever there are decimals
+        let precision = Some(problem.precision())
+            .filter(|p| p.weights > 0 || p.values > 0);
         Self {
             task: Uuid::new_v4(),
             status: Status::Submitted,
@@ -71,12 +393,25 @@ impl Knapsack {
                 started: Option::None,
                 completed: Option::None,
             },
-            problem,
+            problem: Problem { algorithm: Some(algorithm), precision, ..problem },
+            revision: None,
             solution: MyOption::<Solution>::None(Empty {}),
         }
     }
 
     pub fn from_task(task: &DbTask) -> ApiResult<Self> {
+        let precision = Precision {
+            weights: map_int_inv(task.problem_weight_scale)?,
+            values: map_int_inv(task.problem_value_scale)?,
+        };
+        let unscale = |n: i64, scale: u32| -> ApiResult<Decimal> {
+            Ok(Decimal::from_scaled(map_int_inv(n)?, scale))
+        };
+        let unscale_arr = |arr: &[Option<i64>], scale: u32| -> ApiResult<Vec<Decimal>> {
+            map_arr_inv::<i64, u128>(arr).map(|arr| {
+                arr.into_iter().map(|n| Decimal::from_scaled(n, scale)).collect()
+            })
+        };
         Ok(Self {
             task: task.id,
             status: Status::from_str(task.status.as_str())?,
@@ -86,53 +421,196 @@ impl Knapsack {
                 completed: task.ts_completed,
             },
             problem: Problem {
-                capacity: task.problem_capacity as u32,
-                weights: map_arr_inv(&task.problem_weights)?,
-                values: map_arr_inv(&task.problem_values)?,
+                capacity: unscale(task.problem_capacity, precision.weights)?,
+                weights: unscale_arr(&task.problem_weights, precision.weights)?,
+                values: unscale_arr(&task.problem_values, precision.values)?,
+                precision: Some(precision).filter(|p| p.weights > 0 || p.values > 0),
+                algorithm: Some(Algorithm::from_str(task.algorithm.as_str())?),
+                epsilon: task.epsilon,
+                initial_solution: task.initial_solution.as_deref()
+                    .map(map_arr_inv).transpose()?,
+                sensitivity: task.sensitivity,
+                alternatives: task.alternatives
+                    .map(|count| -> ApiResult<_> {
+                        Ok(Alternatives {
+                            count: map_int_inv(count)?,
+                            optimal_only: task.optimal_only,
+                        })
+                    })
+                    .transpose()?,
+                tie_break: task.tie_break.as_deref()
+                    .map(TieBreak::from_str)
+                    .transpose()?,
+                deviations: task.problem_deviations.as_deref()
+                    .map(|arr| unscale_arr(arr, precision.weights))
+                    .transpose()?,
+                gamma: task.gamma.map(map_int_inv).transpose()?,
+                max_items: task.max_items.map(map_int_inv).transpose()?,
+                categories: task.categories.clone(),
+                category_limits: match (&task.category_names, &task.category_limits) {
+                    (Some(names), Some(limits)) => Some(names.iter()
+                        .zip(map_arr_inv::<i32, u32>(limits)?)
+                        .map(|(name, limit)| Ok((name.clone().ok_or_else(null_entry)?, limit)))
+                        .collect::<ApiResult<_>>()?),
+                    _ => None,
+                },
+                constraints: match (&task.constraint_coefficients, &task.constraint_bounds) {
+                    (Some(coefficients), Some(bounds)) => {
+                        let coefficients: Vec<f64> = map_arr_inv(coefficients)?;
+                        let bounds: Vec<f64> = map_arr_inv(bounds)?;
+                        let n = task.problem_weights.len();
+                        if coefficients.len() != n * bounds.len() {
+                            return Err(ApiError::internal(format!(
+                                "Task {} has {} coefficients for {} items and {} constraints",
+                                task.id, coefficients.len(), n, bounds.len())));
+                        }
+                        Some(bounds.into_iter()
+                            .enumerate()
+                            .map(|(j, bound)| Constraint {
+                                coefficients: coefficients[j * n..(j + 1) * n].to_vec(),
+                                bound,
+                            })
+                            .collect())
+                    },
+                    _ => None,
+                },
+                owner: task.owner.clone(),
+                tags: task.tags.as_ref()
+                    .map(|tags| tags.iter().cloned().collect::<Option<_>>().ok_or_else(null_entry))
+                    .transpose()?,
+                callback_url: task.callback_url.clone(),
             },
+            revision: task.parent_id
+                .map(|parent| -> ApiResult<_> {
+                    Ok(Revision {
+                        parent,
+                        removed_items: map_arr_inv(
+                            task.removed_items.as_deref().unwrap_or_default())?,
+                        inherited_bound: task.inherited_bound.map(map_int_inv).transpose()?,
+                    })
+                })
+                .transpose()?,
             solution: MyOption::<Solution>::None(Empty {}),
         })
     }
 
     pub fn set(self, solution: DbSolution) -> ApiResult<Self> {
         Ok(Self {
-            solution: MyOption::Some(Solution {
-                packed_items: map_arr_inv(&solution.packed_items)?,
-                total_value: solution.total_value as u32,
-            }),
+            solution: MyOption::Some(self.solution(solution)?),
             ..self
         })
     }
+
+    /// Converts a stored solution of this knapsack
+    pub fn solution(&self, solution: DbSolution) -> ApiResult<Solution> {
+        let scaled = self.problem.scaled()
+            .ok_or_else(|| ApiError::internal(format!("Problem of task {} could not be scaled", self.task)))?;
+        let precision = scaled.precision;
+        let packed_items = map_arr_inv(&solution.packed_items)?;
+        let total_value = Decimal::from_scaled(
+            map_int_inv(solution.total_value)?, precision.values);
+
+        // Refuse to hand out a stored solution which doesn't solve the problem
+        let verification = crate::verify::verify(
+            &scaled, &packed_items, Some(total_value));
+        if !verification.valid {
+            return Err(ApiError::internal(format!("Stored solution of task {} is invalid: {}",
+                                                  self.task, verification.violations.join(", "))));
+        }
+
+        let guarantee = match (self.problem.epsilon, solution.upper_bound) {
+            (Some(epsilon), Some(upper_bound)) => Some(Guarantee {
+                epsilon,
+                upper_bound: Decimal::from_scaled(map_int_inv(upper_bound)?, precision.values),
+            }),
+            _ => None,
+        };
+
+        // Alternatives are worse than the best solution by design, so only
+        // the best solution is compared to the initial solution
+        let warm_start = match &self.problem.initial_solution {
+            Some(initial_solution) if solution.rank == 0 => {
+                let initial = crate::verify::verify(&scaled, initial_solution, None);
+                Some(WarmStart {
+                    improved: initial.total_value != verification.total_value,
+                    initial_value: initial.total_value,
+                })
+            },
+            _ => None,
+        };
+
+        Ok(Solution {
+            packed_items,
+            total_value,
+            total_weight: verification.total_weight,
+            guarantee,
+            warm_start,
+            worst_case_weight: verification.worst_case_weight,
+            changes: None,
+        })
+    }
+
+    /// Reports how the solution differs from the solution of the parent, if
+    /// this is a revision
+    pub fn compare_to_parent(self, parent: &Knapsack) -> Self {
+        match (&self.revision, self.solution, &parent.solution) {
+            (Some(revision), MyOption::Some(solution), MyOption::Some(parent)) => {
+                let changes = Some(revision.changes(parent, &solution));
+                Self { solution: MyOption::Some(Solution { changes, ..solution }), ..self }
+            },
+            (_, solution, _) => Self { solution, ..self },
+        }
+    }
 }
 
 // Redefining the Option type is a bit terrible, but it's an easy hack
 // to serialize Option::None to `{}` (as in the given sample example
 // inputs) instead of `null`.
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(untagged)]
 pub enum MyOption<T> {
     None(Empty),
     Some(T),
 }
 
-#[derive(Serialize)]
+/// No solution, which is `{}` rather than `null`
+#[derive(Serialize, ToSchema)]
 pub struct Empty {}
 
-#[derive(Serialize)]
+#[derive(Serialize, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
     Submitted,
     Started,
     Completed,
+    Failed,
+    Cancelled,
 }
 impl Status {
-    fn from_str(status: &str) -> ApiResult<Self> {
+    pub fn from_str(status: &str) -> ApiResult<Self> {
         use Status::*;
         match status {
             "submitted" => Ok(Submitted),
             "started" => Ok(Started),
             "completed" => Ok(Completed),
-            _ => Err(HttpStatus::new(500))
+            "failed" => Ok(Failed),
+            "cancelled" => Ok(Cancelled),
+            _ => Err(ApiError::internal(format!("Unknown status {}", status)))
+        }
+    }
+
+    /// Whether a task with this status has reached `status`, or never will,
+    /// as it has finished otherwise
+    pub fn reached(&self, status: &Status) -> bool {
+        self.progress() >= status.progress()
+    }
+
+    fn progress(&self) -> u8 {
+        use Status::*;
+        match self {
+            Submitted => 0,
+            Started => 1,
+            Completed | Failed | Cancelled => 2,
         }
     }
 }
@@ -143,21 +621,156 @@ impl Display for Status {
             Status::Submitted => write!(f,"submitted"),
             Status::Started => write!(f,"started"),
             Status::Completed => write!(f,"completed"),
+            Status::Failed => write!(f,"failed"),
+            Status::Cancelled => write!(f,"cancelled"),
         }
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Timestamps {
     pub submitted: i32,
     pub started: Option<i32>,
     pub completed: Option<i32>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Solution {
     // array of integers (indices to weights and values)
     pub packed_items: Vec<u32>,
     // sum of value of packed_items
-    pub total_value: u32,
+    pub total_value: Decimal,
+    // sum of weight of packed_items
+    pub total_weight: Decimal,
+    // only set for approximate solutions with a proven bound
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guarantee: Option<Guarantee>,
+    // only set if the problem has an initial solution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warm_start: Option<WarmStart>,
+    // only set for robust problems
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worst_case_weight: Option<Decimal>,
+    // only set for the best solution of a revision
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Changes>,
+}
+
+/// How the solution compares to the initial solution it started from. The
+/// solvers never return anything worse, so a different value is an improvement.
+#[derive(Serialize, ToSchema)]
+pub struct WarmStart {
+    pub initial_value: Decimal,
+    pub improved: bool,
+}
+
+/// All solutions of a task, ranked by decreasing value. The first one is the
+/// solution of the knapsack, and the rest are only found if the problem asks
+/// for alternatives.
+#[derive(Serialize, ToSchema)]
+pub struct Solutions {
+    pub task: Uuid,
+    pub status: Status,
+    pub solutions: Vec<Solution>,
+}
+
+/// The guarantee given by an approximation scheme: `total_value` is at least
+/// (1 - epsilon) times the optimum, and the optimum is at most `upper_bound`
+#[derive(Serialize, ToSchema)]
+pub struct Guarantee {
+    pub epsilon: f64,
+    pub upper_bound: Decimal,
+}
+
+/// How sensitive the solution of a task is to changes of its problem. All
+/// numbers are in the units of the problem.
+#[derive(Serialize, ToSchema)]
+pub struct SensitivityReport {
+    pub task: Uuid,
+    // the optimal value, which may be better than the solution found
+    pub optimal_value: Decimal,
+    // the value of one more unit of capacity in the LP relaxation
+    pub capacity_price: f64,
+    pub items: Vec<ItemSensitivity>,
+    // the optimal value of the next few larger capacities
+    pub capacity_increments: Vec<CapacityIncrement>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ItemSensitivity {
+    pub index: u32,
+    pub packed: bool,
+    // value minus the value of its weight at the capacity price
+    pub reduced_cost: f64,
+    // packed items: the lowest value at which the item is still worth packing,
+    // other items: the value at which it becomes worth packing
+    pub value_threshold: Option<Decimal>,
+    // the capacity missing to add the item to the packing, if it isn't packed
+    pub extra_capacity: Option<Decimal>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CapacityIncrement {
+    pub capacity: Decimal,
+    pub total_value: Decimal,
+    // compared to the optimal value
+    pub gain: Decimal,
+}
+
+impl SensitivityReport {
+    pub fn from_db(knapsack: &Knapsack, report: DbSensitivityReport) -> ApiResult<Self> {
+        let precision = knapsack.problem.precision();
+        let packed_items = match &knapsack.solution {
+            MyOption::Some(solution) => &solution.packed_items[..],
+            MyOption::None(_) => &[],
+        };
+        let weights = |n: i64| -> ApiResult<Decimal> {
+            Ok(Decimal::from_scaled(map_int_inv(n)?, precision.weights))
+        };
+        let values = |n: i64| -> ApiResult<Decimal> {
+            Ok(Decimal::from_scaled(map_int_inv(n)?, precision.values))
+        };
+
+        // The analysis is done on the scaled integers, so the prices must be
+        // scaled back to the units of the problem
+        let value_unit = 10f64.powi(precision.values as i32);
+        let weight_unit = 10f64.powi(precision.weights as i32);
+
+        let reduced_costs: Vec<f64> = map_arr_inv(&report.reduced_costs)?;
+        let items = reduced_costs.iter()
+            .zip(&report.value_thresholds)
+            .zip(&report.extra_capacities)
+            .enumerate()
+            .map(|(index, ((reduced_cost, value_threshold), extra_capacity))| {
+                Ok(ItemSensitivity {
+                    index: index as u32,
+                    packed: packed_items.contains(&(index as u32)),
+                    reduced_cost: reduced_cost / value_unit,
+                    value_threshold: value_threshold.map(values).transpose()?,
+                    extra_capacity: extra_capacity.map(weights).transpose()?,
+                })
+            })
+            .collect::<ApiResult<_>>()?;
+
+        let capacities: Vec<i64> = map_arr_inv(&report.capacity_increments)?;
+        let capacity_values: Vec<i64> = map_arr_inv(&report.capacity_values)?;
+        let capacity_increments = capacities.iter()
+            .zip(capacity_values)
+            .map(|(capacity, value)| {
+                Ok(CapacityIncrement {
+                    capacity: weights(*capacity)?,
+                    total_value: values(value)?,
+                    gain: values(value - report.optimal_value)?,
+                })
+            })
+            .collect::<ApiResult<_>>()?;
+
+        Ok(Self {
+            task: knapsack.task,
+            optimal_value: values(report.optimal_value)?,
+            capacity_price: report.capacity_price * weight_unit / value_unit,
+            items,
+            capacity_increments,
+        })
+    }
 }
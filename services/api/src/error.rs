@@ -0,0 +1,198 @@
+//! Errors of the api, which are rendered as problem details (RFC 9457) with
+//! the content type `application/problem+json`, e.g.
+//!
+//! ```json
+//! {"type": "about:blank", "title": "Bad Request", "status": 400,
+//!  "code": "invalid_request", "detail": "Must be between 0 and 1, exclusive",
+//!  "errors": [{"field": "/problem/epsilon", "message": "Must be between 0 and 1, exclusive"}]}
+//! ```
+//!
+//! `code` is meant for programs, and doesn't change, while `detail` is meant
+//! for humans. Invalid requests list the offending fields, as JSON pointers
+//! into the body or as names of parameters and headers.
+
+use std::fmt::Display;
+
+use rocket::form::Errors;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json;
+use serde::{Serialize, Serializer};
+use utoipa::openapi::schema::Schema;
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: Status,
+    code: &'static str,
+    detail: String,
+    errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// The body of an error response
+#[derive(Serialize, ToSchema)]
+struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    code: &'static str,
+    detail: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError],
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: &'static str, detail: impl Into<String>) -> Self {
+        Self { status, code, detail: detail.into(), errors: vec![] }
+    }
+
+    /// The request is invalid because of the field
+    pub fn invalid(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::invalid_fields(vec![FieldError { field: field.into(), message: message.into() }])
+    }
+
+    /// The request is invalid because of the fields, of which there's at
+    /// least one
+    pub fn invalid_fields(errors: Vec<FieldError>) -> Self {
+        let detail = match &errors[..] {
+            [error] => error.message.clone(),
+            _ => format!("{} fields are invalid", errors.len()),
+        };
+        Self { errors, ..Self::new(Status::BadRequest, "invalid_request", detail) }
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(Status::NotFound, "not_found", detail)
+    }
+
+    /// Something which shouldn't happen did, e.g. the db holds a task which
+    /// the api wouldn't have stored. The detail is logged, but not returned.
+    pub fn internal(detail: impl Display) -> Self {
+        eprintln!("Internal error: {}", detail);
+        Self::new(Status::InternalServerError, "internal_error", "Internal error")
+    }
+
+    pub fn with_field(mut self, field: impl Into<String>, message: impl Into<String>) -> Self {
+        self.errors.push(FieldError { field: field.into(), message: message.into() });
+        self
+    }
+
+    /// Moves the fields below `prefix`, e.g. the fields of a problem below
+    /// `/problem` where it's part of the body
+    pub fn at(mut self, prefix: &str) -> Self {
+        for error in &mut self.errors {
+            error.field.insert_str(0, prefix);
+        }
+        self
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// A body which isn't JSON is malformed, while JSON which isn't what's
+    /// expected can't be processed
+    pub fn from_json(e: &serde_json::Error) -> Self {
+        if e.is_data() {
+            Self::new(Status::UnprocessableEntity, "invalid_json", e.to_string())
+        } else {
+            Self::new(Status::BadRequest, "malformed_json", e.to_string())
+        }
+    }
+
+    /// A body read by the `Json` guard, which only fails with an I/O error if
+    /// it's too large, or couldn't be read at all
+    pub fn from_json_guard(e: json::Error<'_>) -> Self {
+        match e {
+            json::Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Self::new(Status::PayloadTooLarge, "too_large", "The body is too large")
+            },
+            json::Error::Io(e) => {
+                Self::new(Status::BadRequest, "unreadable_body", format!("Could not read the body: {}", e))
+            },
+            json::Error::Parse(_, e) => Self::from_json(&e),
+        }
+    }
+
+    /// Query parameters which couldn't be parsed
+    pub fn from_form(errors: Errors<'_>) -> Self {
+        let mut error = Self::new(Status::BadRequest, "invalid_request", "Invalid query parameters");
+        for e in errors {
+            let field = e.name.as_ref().map_or("query".to_string(), |name| name.to_string());
+            error = error.with_field(field, e.kind.to_string());
+        }
+        error
+    }
+
+    /// The error of a status which isn't produced by a route, e.g. 404 for an
+    /// unknown path
+    pub fn from_status(status: Status) -> Self {
+        let code = match status.code {
+            400 => "bad_request",
+            404 => "not_found",
+            405 => "method_not_allowed",
+            413 => "too_large",
+            415 => "unsupported_media_type",
+            422 => "unprocessable",
+            500 => "internal_error",
+            503 => "unavailable",
+            _ => "error",
+        };
+        Self::new(status, code, status.reason_lossy())
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.status.code, self.code, self.detail)
+    }
+}
+
+/// Serializes as the body of the response, e.g. within the results of a batch
+impl Serialize for ApiError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ProblemDetails {
+            problem_type: "about:blank",
+            title: self.status.reason_lossy(),
+            status: self.status.code,
+            code: self.code,
+            detail: &self.detail,
+            errors: &self.errors,
+        }.serialize(serializer)
+    }
+}
+
+impl PartialSchema for ApiError {
+    fn schema() -> RefOr<Schema> {
+        ProblemDetails::schema()
+    }
+}
+
+impl ToSchema for ApiError {}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = serde_json::to_string(&self).map_err(|_| Status::InternalServerError)?;
+        Response::build_from(body.respond_to(request)?)
+            .status(self.status)
+            .header(ContentType::new("application", "problem+json"))
+            .ok()
+    }
+}
+
+/// Renders the errors Rocket responds with itself as problem details too
+#[catch(default)]
+pub fn catch_default(status: Status, _request: &Request) -> ApiError {
+    ApiError::from_status(status)
+}
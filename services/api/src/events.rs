@@ -0,0 +1,120 @@
+//! Streams the changes of a task as server-sent events, see
+//! `GET /knapsack/<id>/events`. A `status` event with the knapsack, as
+//! `GET /knapsack/<id>` returns it, is sent first and then whenever its status
+//! changes, and while the task is running, a `progress` event is sent for each
+//! generation of the genetic solver. The stream ends once the task has
+//! finished.
+
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::sync::broadcast::{error::RecvError, Receiver};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db;
+use crate::decimal::Decimal;
+use crate::dto::{Knapsack, Status};
+use crate::updates::{Progress, Update};
+
+#[derive(Serialize, ToSchema)]
+pub struct ProgressEvent {
+    pub generation: u64,
+    // only set once a feasible packing is found
+    pub best_value: Option<Decimal>,
+    // seconds since the solver started
+    pub elapsed: f64,
+}
+
+impl ProgressEvent {
+    fn new(progress: Progress, knapsack: &Knapsack) -> Self {
+        let scale = knapsack.problem.precision().values;
+        Self {
+            generation: progress.generation,
+            best_value: progress.best_value
+                .and_then(|value| u128::try_from(value).ok())
+                .map(|value| Decimal::from_scaled(value, scale)),
+            elapsed: progress.elapsed,
+        }
+    }
+}
+
+/// What the stream of a task does with an update
+#[derive(Debug)]
+enum Step {
+    // read the task again, and send it if its status changed
+    Read,
+    Progress(Progress),
+    Skip,
+    End,
+}
+
+fn step(update: Result<Update, RecvError>, task: Uuid) -> Step {
+    match update {
+        Ok(Update::Status(id)) if id == task => Step::Read,
+        Ok(Update::Progress(progress)) if progress.task == task => Step::Progress(progress),
+        Ok(_) => Step::Skip,
+        // Some updates were missed, so the task is read again
+        Err(RecvError::Lagged(_)) => Step::Read,
+        Err(RecvError::Closed) => Step::End,
+    }
+}
+
+/// The events of the task, from the updates it was read after
+pub fn stream(mut knapsack: Knapsack, mut updates: Receiver<Update>, conn: &db::Connection)
+              -> EventStream![Event + '_] {
+    EventStream! {
+        yield Event::json(&knapsack).event("status");
+        while !knapsack.status.reached(&Status::Completed) {
+            match step(updates.recv().await, knapsack.task) {
+                Step::Read => {},
+                Step::Progress(progress) => {
+                    yield Event::json(&ProgressEvent::new(progress, &knapsack)).event("progress");
+                    continue;
+                },
+                Step::Skip => continue,
+                Step::End => break,
+            }
+            let Ok(updated) = db::get(knapsack.task, conn) else {
+                break;
+            };
+            if updated.status != knapsack.status {
+                yield Event::json(&updated).event("status");
+            }
+            knapsack = updated;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::Problem;
+
+    const TASK: Uuid = uuid::uuid!("2881a781-9c3d-4eba-b450-290d98c68026");
+    const OTHER: Uuid = uuid::uuid!("f3c7a0c2-5d1e-4a8b-9b7e-0d9f3a6c1e24");
+
+    fn progress(task: Uuid, best_value: Option<i64>) -> Progress {
+        Progress { task, generation: 7, best_value, elapsed: 1.5 }
+    }
+
+    #[test]
+    fn follows_only_the_updates_of_the_task() {
+        assert!(matches!(step(Ok(Update::Status(TASK)), TASK), Step::Read));
+        assert!(matches!(step(Ok(Update::Status(OTHER)), TASK), Step::Skip));
+        assert!(matches!(step(Ok(Update::Progress(progress(TASK, None))), TASK), Step::Progress(_)));
+        assert!(matches!(step(Ok(Update::Progress(progress(OTHER, None))), TASK), Step::Skip));
+        assert!(matches!(step(Err(RecvError::Lagged(3)), TASK), Step::Read));
+        assert!(matches!(step(Err(RecvError::Closed), TASK), Step::End));
+    }
+
+    #[test]
+    fn reports_progress_in_the_units_of_the_values() {
+        let decimals = |numbers: &[&str]| numbers.iter().map(|n| Decimal::parse(n).unwrap()).collect();
+        let problem = Problem::new(Decimal::from(10), decimals(&["4", "5"]), decimals(&["3.25", "4"]));
+        let knapsack = Knapsack::new(problem);
+        let event = |best_value| serde_json::to_value(ProgressEvent::new(progress(TASK, best_value), &knapsack)).unwrap();
+        assert_eq!(event(Some(725)), serde_json::json!({"generation": 7, "best_value": 7.25, "elapsed": 1.5}));
+        assert_eq!(event(None)["best_value"], serde_json::Value::Null);
+        assert_eq!(event(Some(-1))["best_value"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,413 @@
+//! Exports the problem of a task as a mixed integer linear program, in the LP
+//! and MPS text formats read by most MILP solvers (HiGHS, CBC, CPLEX, Gurobi,
+//! GLPK, ...), so that it can be inspected or solved by other tools.
+//!
+//! Item i becomes the binary variable `x<i>`, and the program maximises the
+//! total value subject to the capacity. Robust weights, limits on the number
+//! of items and linear constraints are included the same way the `milp`
+//! solver of the optimizer includes them. The numbers are in the units of the
+//! problem, i.e. not scaled.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::decimal::Decimal;
+use crate::dto::Knapsack;
+use crate::{ApiError, ApiResult};
+
+/// Number of terms written per line of an LP file, as some readers limit the
+/// length of a line
+const TERMS_PER_LINE: usize = 8;
+
+pub enum Format {
+    Lp,
+    Mps,
+}
+
+impl Format {
+    pub fn from_str(format: &str) -> ApiResult<Self> {
+        match format {
+            "lp" => Ok(Format::Lp),
+            "mps" => Ok(Format::Mps),
+            _ => Err(ApiError::invalid("format", "Must be lp or mps")),
+        }
+    }
+}
+
+pub fn export(knapsack: &Knapsack, format: Format) -> String {
+    let model = Model::from(knapsack);
+    match format {
+        Format::Lp => model.lp(),
+        Format::Mps => model.mps(),
+    }
+}
+
+/// A coefficient of a variable, written exactly as it's given
+type Term = (String, String);
+
+enum Sense {
+    AtMost,
+    AtLeast,
+}
+
+/// The row `sum terms <= rhs`, or `>=`
+struct Row {
+    name: String,
+    terms: Vec<Term>,
+    sense: Sense,
+    rhs: String,
+}
+
+struct Model {
+    name: String,
+    comments: Vec<String>,
+    objective: Vec<Term>,
+    rows: Vec<Row>,
+    binaries: Vec<String>,
+    // non-negative continuous variables
+    continuous: Vec<String>,
+}
+
+impl From<&Knapsack> for Model {
+    fn from(knapsack: &Knapsack) -> Self {
+        let problem = &knapsack.problem;
+        let x = |i: usize| format!("x{}", i);
+        let binaries: Vec<String> = (0..problem.weights.len()).map(x).collect();
+        let mut continuous = vec![];
+        let mut rows = vec![];
+        let mut comments = vec![];
+
+        // Zero coefficients are left out, but rows need at least one term
+        let row = |name: String, terms: Vec<Term>, sense: Sense, rhs: String| {
+            let terms = match (terms.is_empty(), binaries.first()) {
+                (true, Some(x)) => vec![(x.clone(), "0".to_string())],
+                _ => terms,
+            };
+            Row { name, terms, sense, rhs }
+        };
+        let nonzero = |d: &&Decimal| d.units != 0;
+
+        let mut capacity: Vec<Term> = problem.weights.iter()
+            .enumerate()
+            .filter(|(_, w)| nonzero(w))
+            .map(|(i, w)| (x(i), w.to_string()))
+            .collect();
+        if let (Some(deviations), Some(gamma)) = (&problem.deviations, problem.gamma) {
+            // The formulation of Bertsimas and Sim, see `milp.rs` of the optimizer
+            comments.push(format!("robust weights with gamma = {}", gamma));
+            continuous.push("z".to_string());
+            capacity.push(("z".to_string(), gamma.to_string()));
+            for (i, d) in deviations.iter().enumerate().filter(|(_, d)| nonzero(d)) {
+                let p = format!("p{}", i);
+                rows.push(row(format!("robust_{}", i),
+                              vec![(p.clone(), "1".to_string()),
+                                   ("z".to_string(), "1".to_string()),
+                                   (x(i), format!("-{}", d))],
+                              Sense::AtLeast, "0".to_string()));
+                capacity.push((p.clone(), "1".to_string()));
+                continuous.push(p);
+            }
+        }
+        rows.insert(0, row("capacity".to_string(), capacity, Sense::AtMost,
+                           problem.capacity.to_string()));
+
+        if let Some(max_items) = problem.max_items {
+            rows.push(row("max_items".to_string(),
+                          binaries.iter().map(|x| (x.clone(), "1".to_string())).collect(),
+                          Sense::AtMost, max_items.to_string()));
+        }
+        if let (Some(categories), Some(limits)) = (&problem.categories, &problem.category_limits) {
+            for (k, (category, limit)) in limits.iter().enumerate() {
+                comments.push(format!("category_{} is {:?}", k, category));
+                let members = categories.iter()
+                    .enumerate()
+                    .filter(|(_, c)| c.as_ref() == Some(category))
+                    .map(|(i, _)| (x(i), "1".to_string()))
+                    .collect();
+                rows.push(row(format!("category_{}", k), members, Sense::AtMost, limit.to_string()));
+            }
+        }
+        for (j, constraint) in problem.constraints.iter().flatten().enumerate() {
+            let terms = constraint.coefficients.iter()
+                .enumerate()
+                .filter(|(_, a)| **a != 0.0)
+                .map(|(i, a)| (x(i), a.to_string()))
+                .collect();
+            rows.push(row(format!("constraint_{}", j), terms, Sense::AtMost,
+                          constraint.bound.to_string()));
+        }
+
+        Model {
+            name: format!("knapsack-{}", knapsack.task),
+            comments,
+            objective: problem.values.iter()
+                .enumerate()
+                .filter(|(_, v)| nonzero(v))
+                .map(|(i, v)| (x(i), v.to_string()))
+                .collect(),
+            rows,
+            binaries,
+            continuous,
+        }
+    }
+}
+
+impl Model {
+    /// The CPLEX LP format
+    fn lp(&self) -> String {
+        let mut lp = format!("\\ {}\n", self.name);
+        for comment in &self.comments {
+            writeln!(lp, "\\ {}", comment).unwrap();
+        }
+        writeln!(lp, "Maximize\n obj: {}", expression(&self.objective)).unwrap();
+        writeln!(lp, "Subject To").unwrap();
+        for row in &self.rows {
+            let sense = match row.sense {
+                Sense::AtMost => "<=",
+                Sense::AtLeast => ">=",
+            };
+            writeln!(lp, " {}: {} {} {}", row.name, expression(&row.terms), sense, row.rhs).unwrap();
+        }
+        // Continuous variables are non-negative by default
+        if !self.binaries.is_empty() {
+            writeln!(lp, "Binaries").unwrap();
+            for chunk in self.binaries.chunks(TERMS_PER_LINE) {
+                writeln!(lp, " {}", chunk.join(" ")).unwrap();
+            }
+        }
+        lp.push_str("End\n");
+        lp
+    }
+
+    /// The free MPS format, where fields are separated by whitespace instead of
+    /// being in fixed columns, which would limit the length of the numbers
+    fn mps(&self) -> String {
+        let mut mps = format!("NAME {}\n", self.name);
+        for comment in &self.comments {
+            writeln!(mps, "* {}", comment).unwrap();
+        }
+        writeln!(mps, "OBJSENSE\n    MAX\nROWS\n N obj").unwrap();
+        for row in &self.rows {
+            let sense = match row.sense {
+                Sense::AtMost => "L",
+                Sense::AtLeast => "G",
+            };
+            writeln!(mps, " {} {}", sense, row.name).unwrap();
+        }
+
+        // The coefficients are listed per variable
+        let mut columns: HashMap<&str, Vec<(&str, &str)>> = HashMap::new();
+        for (variable, coefficient) in &self.objective {
+            columns.entry(variable).or_default().push(("obj", coefficient));
+        }
+        for row in &self.rows {
+            for (variable, coefficient) in &row.terms {
+                columns.entry(variable).or_default().push((&row.name, coefficient));
+            }
+        }
+        writeln!(mps, "COLUMNS").unwrap();
+        for variable in self.binaries.iter().chain(&self.continuous) {
+            for (row, coefficient) in columns.get(variable.as_str()).into_iter().flatten() {
+                writeln!(mps, "    {} {} {}", variable, row, coefficient).unwrap();
+            }
+        }
+
+        writeln!(mps, "RHS").unwrap();
+        for row in self.rows.iter().filter(|row| row.rhs != "0") {
+            writeln!(mps, "    RHS {} {}", row.name, row.rhs).unwrap();
+        }
+        writeln!(mps, "BOUNDS").unwrap();
+        for variable in &self.binaries {
+            writeln!(mps, " BV BND {}", variable).unwrap();
+        }
+        mps.push_str("ENDATA\n");
+        mps
+    }
+}
+
+/// Writes the terms as a sum, e.g. `3 x0 - 2.5 x1 + x2`, wrapping long sums
+fn expression(terms: &[Term]) -> String {
+    let mut expression = String::new();
+    for (k, (variable, coefficient)) in terms.iter().enumerate() {
+        if k > 0 && k % TERMS_PER_LINE == 0 {
+            expression.push_str("\n  ");
+        } else if k > 0 {
+            expression.push(' ');
+        }
+        let (sign, magnitude) = match coefficient.strip_prefix('-') {
+            Some(magnitude) => ("- ", magnitude),
+            None if k > 0 => ("+ ", coefficient.as_str()),
+            None => ("", coefficient.as_str()),
+        };
+        write!(expression, "{}{} {}", sign, magnitude, variable).unwrap();
+    }
+    expression
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::iter::Peekable;
+
+    use super::*;
+    use crate::dto::{Constraint, Problem};
+
+    /// A row read back from a file, with its coefficients by variable
+    #[derive(Debug, PartialEq)]
+    struct ReadRow {
+        terms: BTreeMap<String, String>,
+        sense: &'static str,
+        rhs: String,
+    }
+
+    /// A program read back from a file, with the rows by name
+    #[derive(Debug, PartialEq)]
+    struct Read {
+        objective: BTreeMap<String, String>,
+        rows: BTreeMap<String, ReadRow>,
+        binaries: Vec<String>,
+    }
+
+    /// Reads the terms of an LP expression, up to its sense or the end of the
+    /// objective
+    fn read_terms<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> BTreeMap<String, String> {
+        let mut terms = BTreeMap::new();
+        while let Some(token) = tokens.next_if(|token| !["<=", ">=", "Subject"].contains(token)) {
+            let coefficient = match token {
+                "-" => format!("-{}", tokens.next().unwrap()),
+                "+" => tokens.next().unwrap().to_string(),
+                _ => token.to_string(),
+            };
+            terms.insert(tokens.next().unwrap().to_string(), coefficient);
+        }
+        terms
+    }
+
+    fn read_lp(lp: &str) -> Read {
+        let mut tokens = lp.lines()
+            .filter(|line| !line.starts_with('\\'))
+            .flat_map(str::split_whitespace)
+            .peekable();
+        assert_eq!(tokens.next(), Some("Maximize"));
+        assert_eq!(tokens.next(), Some("obj:"));
+        let objective = read_terms(&mut tokens);
+        assert_eq!((tokens.next(), tokens.next()), (Some("Subject"), Some("To")));
+        let mut rows = BTreeMap::new();
+        while let Some(name) = tokens.next().and_then(|token| token.strip_suffix(':')) {
+            let terms = read_terms(&mut tokens);
+            let sense = if tokens.next() == Some("<=") { "<=" } else { ">=" };
+            rows.insert(name.to_string(), ReadRow { terms, sense, rhs: tokens.next().unwrap().to_string() });
+        }
+        let binaries = tokens.take_while(|token| *token != "End").map(str::to_string).collect();
+        Read { objective, rows, binaries }
+    }
+
+    fn read_mps(mps: &str) -> Read {
+        let mut read = Read { objective: BTreeMap::new(), rows: BTreeMap::new(), binaries: vec![] };
+        let mut section = "";
+        for line in mps.lines().filter(|line| !line.starts_with('*')) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if !line.starts_with(' ') {
+                section = fields[0];
+                continue;
+            }
+            match (section, &fields[..]) {
+                ("OBJSENSE", ["MAX"]) | ("ROWS", ["N", "obj"]) => {},
+                ("ROWS", [sense, name]) => {
+                    let sense = if *sense == "L" { "<=" } else { ">=" };
+                    let row = ReadRow { terms: BTreeMap::new(), sense, rhs: "0".to_string() };
+                    read.rows.insert(name.to_string(), row);
+                },
+                ("COLUMNS", [variable, "obj", coefficient]) => {
+                    read.objective.insert(variable.to_string(), coefficient.to_string());
+                },
+                ("COLUMNS", [variable, row, coefficient]) => {
+                    read.rows.get_mut(*row).unwrap().terms.insert(variable.to_string(), coefficient.to_string());
+                },
+                ("RHS", ["RHS", row, rhs]) => read.rows.get_mut(*row).unwrap().rhs = rhs.to_string(),
+                ("BOUNDS", ["BV", "BND", variable]) => read.binaries.push(variable.to_string()),
+                _ => panic!("Unexpected line in section {}: {}", section, line),
+            }
+        }
+        read
+    }
+
+    /// The capacity, weights, values and constraints of the program, in the
+    /// units they were written in
+    fn knapsack_of(read: &Read, n: usize) -> (String, Vec<String>, Vec<String>, Vec<Constraint>) {
+        let coefficient = |terms: &BTreeMap<String, String>, i: usize| {
+            terms.get(&format!("x{}", i)).cloned().unwrap_or("0".to_string())
+        };
+        let capacity = &read.rows["capacity"];
+        let constraints = (0..)
+            .map_while(|j| read.rows.get(&format!("constraint_{}", j)))
+            .map(|row| Constraint {
+                coefficients: (0..n).map(|i| coefficient(&row.terms, i).parse().unwrap()).collect(),
+                bound: row.rhs.parse().unwrap(),
+            })
+            .collect();
+        (capacity.rhs.clone(),
+         (0..n).map(|i| coefficient(&capacity.terms, i)).collect(),
+         (0..n).map(|i| coefficient(&read.objective, i)).collect(),
+         constraints)
+    }
+
+    fn problem(capacity: &str, weights: &[&str], values: &[&str]) -> Problem {
+        let decimals = |numbers: &[&str]| numbers.iter().map(|n| Decimal::parse(n).unwrap()).collect();
+        Problem::new(Decimal::parse(capacity).unwrap(), decimals(weights), decimals(values))
+    }
+
+    #[test]
+    fn reads_back_the_same_problem() {
+        // More items than fit on a line, with decimals and zeros
+        let weights = ["4", "5.25", "0", "7", "1", "2", "3", "4", "5", "6.5"];
+        let values = ["3", "4.10", "2", "0", "1", "1", "1", "1", "1", "12"];
+        let problem = Problem {
+            constraints: Some(vec![
+                Constraint { coefficients: vec![-1.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0], bound: -0.5 },
+                Constraint { coefficients: vec![0.0; 10], bound: 3.0 },
+            ]),
+            ..problem("20.5", &weights, &values)
+        };
+        let knapsack = Knapsack::new(problem.clone());
+
+        let lp = read_lp(&export(&knapsack, Format::Lp));
+        let mps = read_mps(&export(&knapsack, Format::Mps));
+        assert_eq!(lp, mps);
+
+        let (capacity, weights, values, constraints) = knapsack_of(&lp, 10);
+        assert_eq!(capacity, problem.capacity.to_string());
+        assert_eq!(weights, problem.weights.iter().map(Decimal::to_string).collect::<Vec<_>>());
+        assert_eq!(values, problem.values.iter().map(Decimal::to_string).collect::<Vec<_>>());
+        assert_eq!(Some(constraints), problem.constraints);
+        assert_eq!(lp.binaries, (0..10).map(|i| format!("x{}", i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn writes_robust_weights_and_limits_as_rows() {
+        let problem = Problem {
+            deviations: Some(vec![Decimal::from(2), Decimal::from(0), Decimal::from(3)]),
+            gamma: Some(1),
+            max_items: Some(2),
+            categories: Some(vec![Some("a".to_string()), None, Some("a".to_string())]),
+            category_limits: Some(BTreeMap::from([("a".to_string(), 1)])),
+            ..problem("10", &["4", "5", "6"], &["3", "4", "5"])
+        };
+        let knapsack = Knapsack::new(problem);
+        let lp = read_lp(&export(&knapsack, Format::Lp));
+        assert_eq!(lp, read_mps(&export(&knapsack, Format::Mps)));
+
+        let terms = |terms: &[(&str, &str)]| terms.iter()
+            .map(|(variable, coefficient)| (variable.to_string(), coefficient.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(lp.rows["capacity"].terms,
+                   terms(&[("x0", "4"), ("x1", "5"), ("x2", "6"), ("z", "1"), ("p0", "1"), ("p2", "1")]));
+        assert_eq!(lp.rows["robust_0"],
+                   ReadRow { terms: terms(&[("p0", "1"), ("z", "1"), ("x0", "-2")]), sense: ">=", rhs: "0".to_string() });
+        assert!(!lp.rows.contains_key("robust_1"));
+        assert_eq!(lp.rows["max_items"],
+                   ReadRow { terms: terms(&[("x0", "1"), ("x1", "1"), ("x2", "1")]), sense: "<=", rhs: "2".to_string() });
+        assert_eq!(lp.rows["category_0"],
+                   ReadRow { terms: terms(&[("x0", "1"), ("x2", "1")]), sense: "<=", rhs: "1".to_string() });
+    }
+}
@@ -0,0 +1,85 @@
+//! Makes retrying `POST /knapsack` safe: a request with an `Idempotency-Key`
+//! header creates a task only the first time the key is used. Later requests
+//! with the same key get that task instead, as long as they're for the same
+//! problem, and are rejected with 409 otherwise. The key is stored with the
+//! task, along with a hash of the request.
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+
+use crate::dto::ProblemBody;
+use crate::{ApiError, ApiResult};
+
+pub const HEADER: &str = "Idempotency-Key";
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// The idempotency key of the request, if it has one
+pub struct IdempotencyKey(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one(HEADER) {
+            None => Outcome::Success(IdempotencyKey(None)),
+            Some(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => {
+                Outcome::Success(IdempotencyKey(Some(key.to_string())))
+            },
+            Some(_) => Outcome::Error((Status::BadRequest, ApiError::invalid(
+                HEADER, format!("Must have 1 to {} characters", MAX_KEY_LENGTH)))),
+        }
+    }
+}
+
+/// The SHA-256 of the request as it was parsed, in hex, so that requests
+/// which only differ by whitespace or the order of the fields are the same
+pub fn hash(body: &ProblemBody) -> ApiResult<String> {
+    let json = serde_json::to_vec(body).map_err(ApiError::internal)?;
+    Ok(Sha256::digest(json).iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Whether a request with the hash may replay the task stored with the same
+/// key, which it may only if it's for the same problem
+pub fn check_replay(original_hash: Option<&str>, request_hash: &str) -> ApiResult<()> {
+    if original_hash != Some(request_hash) {
+        return Err(ApiError::new(Status::Conflict, "idempotency_key_reused",
+                                 "The idempotency key was already used for a different problem"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_of(json: &str) -> String {
+        hash(&serde_json::from_str(json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn replays_the_same_problem() {
+        let original = hash_of(r#"{"problem": {"capacity": 60, "weights": [10, 20], "values": [10, 3]}}"#);
+        let repeated = hash_of(r#"{"problem": {"capacity": 60, "weights": [10, 20], "values": [10, 3]}}"#);
+        assert!(check_replay(Some(&original), &repeated).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_different_problem_with_409() {
+        let original = hash_of(r#"{"problem": {"capacity": 60, "weights": [10, 20], "values": [10, 3]}}"#);
+        let different = hash_of(r#"{"problem": {"capacity": 61, "weights": [10, 20], "values": [10, 3]}}"#);
+        assert_eq!(check_replay(Some(&original), &different).err().unwrap().status(), Status::Conflict);
+        // Nor can a task stored without a hash
+        assert_eq!(check_replay(None, &original).err().unwrap().status(), Status::Conflict);
+    }
+
+    #[test]
+    fn ignores_whitespace_and_the_order_of_the_fields() {
+        let original = hash_of(r#"{"problem": {"capacity": 60, "weights": [10, 20], "values": [10, 3],
+                                               "category_limits": {"a": 1, "b": 2}}}"#);
+        let reordered = hash_of(r#"{"problem":{"category_limits":{"b":2,"a":1},"values":[10,3],
+                                               "weights":[10,20],"capacity":60}}"#);
+        assert_eq!(original, reordered);
+    }
+}
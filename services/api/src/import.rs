@@ -0,0 +1,255 @@
+//! Imports knapsack instances from the text formats of classic benchmark
+//! collections, converting them into problems which can be submitted as they
+//! are. Known optima and solutions in the files are ignored.
+//!
+//! - `or_library`: The multidimensional knapsack format of Beasley's
+//!   OR-Library (`mknap1.txt`, `mknapcb*.txt`): the number of instances, and
+//!   per instance the number of items n, the number of dimensions m and the
+//!   optimum, followed by the n values, the m rows of n weights and the m
+//!   capacities. The first dimension becomes the weights and capacity of the
+//!   problem, the rest become linear constraints.
+//! - `pisinger`: The format of Pisinger's instances (`knapPI_*.csv`): per
+//!   instance a name, lines `n <items>`, `c <capacity>`, `z <optimum>` and
+//!   `time <seconds>`, a line `<index>,<value>,<weight>,<packed>` per item,
+//!   and a line of dashes.
+//!
+//! [OR-Library](https://people.brunel.ac.uk/~mastjjb/jeb/orlib/mknapinfo.html)
+//! [Pisinger](http://hjemmesider.diku.dk/~pisinger/codes.html)
+
+use rocket::http::Status;
+
+use crate::decimal::Decimal;
+use crate::dto::{Constraint, Problem};
+use crate::validation::Limits;
+use crate::{ApiError, ApiResult};
+
+/// Maximum size of an imported file, in MiB
+pub const MAX_SIZE_MIB: u64 = 64;
+
+pub enum Format {
+    OrLibrary,
+    Pisinger,
+}
+
+impl Format {
+    pub fn from_str(format: &str) -> ApiResult<Self> {
+        match format {
+            "or_library" => Ok(Format::OrLibrary),
+            "pisinger" => Ok(Format::Pisinger),
+            _ => Err(ApiError::invalid("format", "Must be or_library or pisinger")),
+        }
+    }
+}
+
+/// All instances of the file, which must be valid problems. The fields of an
+/// invalid one are given below its index, e.g. `/2/capacity`.
+pub fn import(format: Format, text: &str, limits: &Limits) -> ApiResult<Vec<Problem>> {
+    let problems = match format {
+        Format::OrLibrary => or_library(text),
+        Format::Pisinger => pisinger(text),
+    }.ok_or_else(|| ApiError::new(Status::BadRequest, "malformed_file",
+                                  "The file is not in the given format"))?;
+    for (i, problem) in problems.iter().enumerate() {
+        problem.validate(limits).map_err(|e| e.at(&format!("/{}", i)))?;
+    }
+    Ok(problems)
+}
+
+fn or_library(text: &str) -> Option<Vec<Problem>> {
+    let mut tokens = text.split_whitespace();
+    let mut next = || tokens.next();
+    let count: usize = next()?.parse().ok()?;
+
+    let mut problems = vec![];
+    for _ in 0..count {
+        let n: usize = next()?.parse().ok()?;
+        let m: usize = next()?.parse().ok()?;
+        let _optimum = next()?;
+        let values = (0..n).map(|_| Decimal::parse(next()?)).collect::<Option<Vec<_>>>()?;
+        let rows = (0..m)
+            .map(|_| (0..n).map(|_| next()).collect::<Option<Vec<_>>>())
+            .collect::<Option<Vec<_>>>()?;
+        let capacities = (0..m).map(|_| next()).collect::<Option<Vec<_>>>()?;
+
+        let (weights, rows) = rows.split_first()?;
+        let (capacity, capacities) = capacities.split_first()?;
+        let mut problem = Problem::new(
+            Decimal::parse(capacity)?,
+            weights.iter().map(|w| Decimal::parse(w)).collect::<Option<_>>()?,
+            values);
+        if !rows.is_empty() {
+            let parse = |s: &str| s.parse::<f64>().ok();
+            problem.constraints = Some(rows.iter()
+                .zip(capacities)
+                .map(|(row, bound)| Some(Constraint {
+                    coefficients: row.iter().map(|a| parse(a)).collect::<Option<_>>()?,
+                    bound: parse(bound)?,
+                }))
+                .collect::<Option<_>>()?);
+        }
+        problems.push(problem);
+    }
+
+    // Anything after the announced instances means the file was misread
+    next().is_none().then_some(problems)
+}
+
+fn pisinger(text: &str) -> Option<Vec<Problem>> {
+    let mut problems = vec![];
+    let mut n = None;
+    let mut capacity = None;
+    let mut weights = vec![];
+    let mut values = vec![];
+
+    for line in text.lines().map(str::trim).chain(std::iter::once("-----")) {
+        if line.starts_with("-----") {
+            // The end of an instance, or of the file
+            if let Some(capacity) = capacity.take() {
+                if n.take() != Some(weights.len()) {
+                    return None;
+                }
+                problems.push(Problem::new(capacity, std::mem::take(&mut weights),
+                                           std::mem::take(&mut values)));
+            } else if n.is_some() || !weights.is_empty() {
+                return None;
+            }
+        } else if let Some(items) = line.strip_prefix("n ") {
+            n = Some(items.trim().parse().ok()?);
+        } else if let Some(c) = line.strip_prefix("c ") {
+            capacity = Some(Decimal::parse(c.trim())?);
+        } else if line.contains(',') {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [_, value, weight, ..] = fields[..] else {
+                return None;
+            };
+            values.push(Decimal::parse(value)?);
+            weights.push(Decimal::parse(weight)?);
+        }
+        // Names, optima, times and empty lines are skipped
+    }
+    Some(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The code of the error and the fields it lists
+    fn rejection(format: Format, text: &str) -> (String, Vec<String>) {
+        let error = serde_json::to_value(import(format, text, &Limits::default()).err().unwrap()).unwrap();
+        let fields = error["errors"].as_array().into_iter().flatten()
+            .map(|e| e["field"].as_str().unwrap().to_string())
+            .collect();
+        (error["code"].as_str().unwrap().to_string(), fields)
+    }
+
+    fn numbers(numbers: &[Decimal]) -> Vec<String> {
+        numbers.iter().map(Decimal::to_string).collect()
+    }
+
+    /// Two instances, the first with a single dimension and the second with
+    /// two, the second of which becomes a constraint
+    const OR_LIBRARY: &str = "2
+ 3 1 0
+ 10 20 30
+ 5 6 7
+ 12
+ 2 2 99
+ 4 5
+ 1 2
+ 3.5 -1
+ 2 0.5
+";
+
+    /// Two instances, the first in the exact format of `knapPI_*.csv`
+    const PISINGER: &str = "knapPI_1_3_1000_1
+n 3
+c 25
+z 60
+time 0.00
+1,10,5,1
+2,20,10,1
+3,30,15,0
+-----
+
+knapPI_1_2_1000_2
+n 2
+c 1.5
+z 4
+time 0.00
+1,4,1.5,1
+2,3,2,0
+-----
+";
+
+    #[test]
+    fn reads_or_library_instances() {
+        let problems = import(Format::OrLibrary, OR_LIBRARY, &Limits::default()).unwrap();
+        assert_eq!(problems.len(), 2);
+
+        assert_eq!(problems[0].capacity.to_string(), "12");
+        assert_eq!(numbers(&problems[0].weights), ["5", "6", "7"]);
+        assert_eq!(numbers(&problems[0].values), ["10", "20", "30"]);
+        assert_eq!(problems[0].constraints, None);
+
+        assert_eq!(problems[1].capacity.to_string(), "2");
+        assert_eq!(numbers(&problems[1].weights), ["1", "2"]);
+        assert_eq!(numbers(&problems[1].values), ["4", "5"]);
+        assert_eq!(problems[1].constraints,
+                   Some(vec![Constraint { coefficients: vec![3.5, -1.0], bound: 0.5 }]));
+    }
+
+    #[test]
+    fn reads_pisinger_instances() {
+        let problems = import(Format::Pisinger, PISINGER, &Limits::default()).unwrap();
+        assert_eq!(problems.len(), 2);
+
+        assert_eq!(problems[0].capacity.to_string(), "25");
+        assert_eq!(numbers(&problems[0].weights), ["5", "10", "15"]);
+        assert_eq!(numbers(&problems[0].values), ["10", "20", "30"]);
+
+        assert_eq!(problems[1].capacity.to_string(), "1.5");
+        assert_eq!(numbers(&problems[1].weights), ["1.5", "2"]);
+        assert_eq!(numbers(&problems[1].values), ["4", "3"]);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let truncated = OR_LIBRARY.trim_end().rsplit_once(' ').unwrap().0;
+        assert_eq!(rejection(Format::OrLibrary, truncated).0, "malformed_file");
+        // The last instance isn't ended by a line of dashes, but by the end
+        // of the file, so only cutting off items is noticed
+        let truncated = PISINGER.trim_end().rsplit_once("2,3,2,0").unwrap().0;
+        assert_eq!(rejection(Format::Pisinger, truncated).0, "malformed_file");
+    }
+
+    #[test]
+    fn rejects_non_numeric_tokens() {
+        for (from, to) in [("2\n", "two\n"), (" 10 20 30", " 10 x 30"), (" 5 6 7", " 5 -6 7"), ("3.5 -1", "3.5 y")] {
+            let text = OR_LIBRARY.replacen(from, to, 1);
+            assert_eq!(rejection(Format::OrLibrary, &text).0, "malformed_file", "{}", text);
+        }
+        for (from, to) in [("n 3", "n three"), ("c 25", "c -25"), ("2,20,10", "2,20,ten"), ("3,30,15,0", "3,30")] {
+            let text = PISINGER.replacen(from, to, 1);
+            assert_eq!(rejection(Format::Pisinger, &text).0, "malformed_file", "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_item_counts_which_dont_match() {
+        // Fewer instances than announced run out of tokens, more leave some
+        assert_eq!(rejection(Format::OrLibrary, &OR_LIBRARY.replacen("2\n", "3\n", 1)).0, "malformed_file");
+        assert_eq!(rejection(Format::OrLibrary, &OR_LIBRARY.replacen("2\n", "1\n", 1)).0, "malformed_file");
+        assert_eq!(rejection(Format::Pisinger, &PISINGER.replacen("n 3", "n 4", 1)).0, "malformed_file");
+        assert_eq!(rejection(Format::Pisinger, &PISINGER.replacen("n 2", "n 1", 1)).0, "malformed_file");
+    }
+
+    #[test]
+    fn points_at_the_invalid_instance() {
+        // Well formed, but with more items than the limits allow
+        let limits = Limits { max_items: 2, ..Limits::default() };
+        let error = serde_json::to_value(import(Format::Pisinger, PISINGER, &limits).err().unwrap()).unwrap();
+        assert_eq!(error["code"], "invalid_request");
+        assert_eq!(error["errors"][0]["field"], "/0/weights");
+    }
+}
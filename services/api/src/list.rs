@@ -0,0 +1,218 @@
+//! Lists the tasks, newest first unless `order` is `asc`, filtered by status,
+//! owner, tags and the time they were submitted. Each page ends with a cursor,
+//! the position of its last task, from which the next page continues, so that
+//! pages neither skip nor repeat tasks while new ones are submitted.
+//!
+//! Tasks are summarised without their items, unless `full` is set.
+
+use serde::Serialize;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::db::models::TaskSummary as DbTaskSummary;
+use crate::db::models::{map_int_inv, null_entry};
+use crate::dto::{Algorithm, Knapsack, Problem, Status, Timestamps};
+use crate::{ApiError, ApiResult};
+
+/// Number of tasks per page, unless the query asks for fewer
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 500;
+
+#[derive(FromForm, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    #[param(value_type = Option<Status>)]
+    pub status: Option<String>,
+    pub owner: Option<String>,
+    // tasks must have all of the tags, given as `tag=a&tag=b`
+    #[field(name = "tag")]
+    #[param(rename = "tag", required = false)]
+    pub tags: Vec<String>,
+    // unix/ epoch times, the first inclusive and the second exclusive
+    pub submitted_after: Option<i32>,
+    pub submitted_before: Option<i32>,
+    pub order: Option<String>,
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
+    #[field(default = false)]
+    #[param(required = false)]
+    pub full: bool,
+}
+
+/// The validated query
+pub struct Filter {
+    pub status: Option<Status>,
+    pub owner: Option<String>,
+    pub tags: Vec<String>,
+    pub submitted_after: Option<i32>,
+    pub submitted_before: Option<i32>,
+    pub ascending: bool,
+    pub limit: u32,
+    pub cursor: Option<Cursor>,
+    pub full: bool,
+}
+
+impl ListQuery {
+    pub fn filter(self) -> ApiResult<Filter> {
+        let ascending = match self.order.as_deref() {
+            None | Some("desc") => false,
+            Some("asc") => true,
+            Some(_) => return Err(ApiError::invalid("order", "Must be asc or desc")),
+        };
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(ApiError::invalid("limit", format!("Must be 1 to {}", MAX_LIMIT)));
+        }
+        Ok(Filter {
+            status: self.status.as_deref()
+                .map(|status| Status::from_str(status).map_err(|_| ApiError::invalid("status", "Must be a status")))
+                .transpose()?,
+            owner: self.owner,
+            tags: self.tags,
+            submitted_after: self.submitted_after,
+            submitted_before: self.submitted_before,
+            ascending,
+            limit,
+            cursor: self.cursor.as_deref()
+                .map(|cursor| Cursor::parse(cursor).ok_or_else(|| ApiError::invalid("cursor", "Must be a cursor of a page")))
+                .transpose()?,
+            full: self.full,
+        })
+    }
+}
+
+/// The position of a task in the list, which is ordered by the time the tasks
+/// were submitted, and then by their ids. It's written as `<time>_<id>`.
+pub struct Cursor {
+    pub submitted: i32,
+    pub task: Uuid,
+}
+
+impl Cursor {
+    fn parse(cursor: &str) -> Option<Self> {
+        let (submitted, task) = cursor.split_once('_')?;
+        Some(Self { submitted: submitted.parse().ok()?, task: Uuid::try_parse(task).ok()? })
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.submitted, self.task)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TaskList {
+    pub tasks: Vec<TaskSummary>,
+    // only set if there are more tasks
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TaskSummary {
+    pub task: Uuid,
+    pub status: Status,
+    pub timestamps: Timestamps,
+    pub algorithm: Algorithm,
+    // number of items of the problem
+    pub items: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // only set if the task is a revision of another task
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Uuid>,
+    // only set if the full problems are asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<Problem>,
+}
+
+impl TaskSummary {
+    /// The summary of a task read without its problem, of which only the
+    /// number of items is known
+    pub fn from_db(task: DbTaskSummary, items: Option<i32>) -> ApiResult<Self> {
+        Ok(Self {
+            task: task.id,
+            status: Status::from_str(&task.status)?,
+            timestamps: Timestamps {
+                submitted: task.ts_submitted,
+                started: task.ts_started,
+                completed: task.ts_completed,
+            },
+            algorithm: Algorithm::from_str(&task.algorithm)?,
+            // The array is null rather than empty without any items
+            items: map_int_inv(items.unwrap_or(0))?,
+            owner: task.owner,
+            tags: task.tags.unwrap_or_default().into_iter()
+                .collect::<Option<_>>()
+                .ok_or_else(null_entry)?,
+            parent: task.parent_id,
+            problem: None,
+        })
+    }
+
+    pub fn from_knapsack(knapsack: Knapsack) -> ApiResult<Self> {
+        let problem = knapsack.problem;
+        Ok(Self {
+            task: knapsack.task,
+            status: knapsack.status,
+            timestamps: knapsack.timestamps,
+            algorithm: problem.algorithm
+                .ok_or_else(|| ApiError::internal(format!("Task {} has no algorithm", knapsack.task)))?,
+            items: map_int_inv(problem.weights.len())?,
+            owner: problem.owner.clone(),
+            tags: problem.tags.clone().unwrap_or_default(),
+            parent: knapsack.revision.map(|revision| revision.parent),
+            problem: Some(problem),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(cursor: Option<&str>) -> ListQuery {
+        ListQuery {
+            status: None,
+            owner: None,
+            tags: vec![],
+            submitted_after: None,
+            submitted_before: None,
+            order: None,
+            limit: None,
+            cursor: cursor.map(str::to_string),
+            full: false,
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        for submitted in [0, 1_700_000_000, -1, i32::MAX] {
+            let cursor = Cursor { submitted, task: Uuid::new_v4() };
+            let parsed = Cursor::parse(&cursor.to_string()).unwrap();
+            assert_eq!((parsed.submitted, parsed.task), (cursor.submitted, cursor.task));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        let task = Uuid::new_v4();
+        for cursor in [String::new(), "1700000000".to_string(), task.to_string(),
+                       format!("_{}", task), format!("x_{}", task), "1700000000_x".to_string(),
+                       format!("{}_{}", i64::from(i32::MAX) + 1, task)] {
+            assert!(Cursor::parse(&cursor).is_none(), "{}", cursor);
+            assert_eq!(query(Some(&cursor)).filter().err().unwrap().status().code, 400);
+        }
+    }
+
+    #[test]
+    fn filter_continues_from_the_cursor() {
+        let task = Uuid::new_v4();
+        let filter = query(Some(&format!("1700000000_{}", task))).filter().unwrap();
+        let cursor = filter.cursor.unwrap();
+        assert_eq!((cursor.submitted, cursor.task), (1_700_000_000, task));
+        assert!(query(None).filter().unwrap().cursor.is_none());
+    }
+}
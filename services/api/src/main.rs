@@ -4,19 +4,46 @@ extern crate rocket;
 mod dto;
 mod queue;
 mod db;
+mod decimal;
+mod verify;
+mod revise;
+mod export;
+mod import;
+mod list;
+mod batch;
+mod idempotency;
+mod webhook;
+mod updates;
+mod events;
+mod error;
+mod validation;
+mod openapi;
 
 use uuid::Uuid;
 use rocket::{Rocket, Orbit, State};
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::serde::json::Json;
-use rocket::http::Status;
+use rocket::serde::json::{self, Json};
+use rocket::form::Errors;
+use rocket::http::{ContentType, Status};
+use rocket::data::{Data, ToByteUnit};
+use rocket::response::stream::{Event, EventStream};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use dto::{Knapsack, ProblemBody};
+use dto::{Knapsack, ProblemBody, SensitivityReport, Solutions};
+use verify::{Verification, VerifyBody};
+use revise::RevisionBody;
+use list::{ListQuery, TaskList};
+use batch::BatchResult;
+use idempotency::IdempotencyKey;
+use webhook::DeliveryLog;
+use updates::TaskUpdates;
+use error::{ApiError, ApiResult};
+use validation::Limits;
+use openapi::Failures;
 
 /// Gets the environment variable `name`, panicking if it's not set
 fn get_var(name: &str) -> String {
-    std::env::var(name).expect(&format!("{} not set", name))
+    std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name))
 }
 
 /// Returns the current unix/ epoch time
@@ -29,16 +56,138 @@ fn current_time() -> i32 {
         .expect("This program is way old and needs maintenance")
 }
 
-type HttpStatus = rocket::http::Status;
-type ApiResult<T> = Result<T, HttpStatus>;
+/// The knapsack of the task, see "Waiting for a task" in the README for `wait` and `until`
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task"),
+           ("wait" = Option<u64>, Query, description = "Seconds to wait for the task to reach `until`"),
+           ("until" = Option<dto::Status>, Query, description = "The status to wait for, `completed` by default")),
+    responses((status = 200, description = "The knapsack of the task", body = Knapsack), Failures),
+)]
+#[get("/knapsack/<id>?<wait>&<until>")]
+async fn status(id: &str, wait: Option<&str>, until: Option<&str>, state: &State<Connections>,
+                updates: &State<TaskUpdates>) -> ApiResult<Json<Knapsack>> {
+    if let Ok(id) = Uuid::try_parse(id) {
+        let wait = wait
+            .map(|wait| wait.parse::<u64>()
+                 .map_err(|_| ApiError::invalid("wait", "Must be a number of seconds")))
+            .transpose()?;
+        let until = until
+            .map(|until| dto::Status::from_str(until)
+                 .map_err(|_| ApiError::invalid("until", "Must be a status")))
+            .transpose()?;
+        match (wait, until) {
+            (None, None) => db::get(id, &state.db_conn).map(Json),
+            // Waits until the task has finished, unless told otherwise
+            (Some(wait), until) if wait <= updates::MAX_WAIT => {
+                let until = until.unwrap_or(dto::Status::Completed);
+                updates::wait(id, until, wait, updates, &state.db_conn).await.map(Json)
+            },
+            (Some(_), _) => Err(ApiError::invalid(
+                "wait", format!("Must be at most {} seconds", updates::MAX_WAIT))),
+            (None, Some(_)) => Err(ApiError::invalid("until", "Requires wait")),
+        }
+    } else {
+        // ill-formed id
+        Err(ApiError::invalid("id", "Must be a UUID"))
+    }
+}
+
+/// A page of the tasks, newest first
+#[utoipa::path(
+    tag = "knapsack",
+    params(ListQuery),
+    responses((status = 200, description = "The page of tasks", body = TaskList), Failures),
+)]
+#[get("/knapsack?<query..>")]
+async fn list_tasks(query: Result<ListQuery, Errors<'_>>, state: &State<Connections>) -> ApiResult<Json<TaskList>> {
+    let filter = query.map_err(ApiError::from_form)?.filter()?;
+    db::list(&filter, &state.db_conn).map(Json)
+}
+
+/// Streams the changes of the task as server-sent events: `status` events with the knapsack, and `progress` events with a `ProgressEvent`
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task")),
+    responses((status = 200, description = "The events", content_type = "text/event-stream", body = String), Failures),
+)]
+#[get("/knapsack/<id>/events")]
+async fn task_events<'a>(id: &str, state: &'a State<Connections>, updates: &State<TaskUpdates>)
+                         -> ApiResult<EventStream![Event + 'a]> {
+    let Ok(id) = Uuid::try_parse(id) else {
+        // ill-formed id
+        return Err(ApiError::invalid("id", "Must be a UUID"));
+    };
+    // Subscribed before the task is read, so that no update is missed
+    let receiver = updates.subscribe();
+    let knapsack = db::get(id, &state.db_conn)?;
+    Ok(events::stream(knapsack, receiver, &state.db_conn))
+}
+
+/// All solutions of the task, ranked by decreasing value
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task")),
+    responses((status = 200, description = "The solutions", body = Solutions), Failures),
+)]
+#[get("/knapsack/<id>/solutions")]
+async fn solutions(id: &str, state: &State<Connections>) -> ApiResult<Json<Solutions>> {
+    if let Ok(id) = Uuid::try_parse(id) {
+        db::get_solutions(id, &state.db_conn).map(Json)
+    } else {
+        // ill-formed id
+        Err(ApiError::invalid("id", "Must be a UUID"))
+    }
+}
+
+/// The log of the delivery of the task to its `callback_url`
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task")),
+    responses((status = 200, description = "The delivery log", body = DeliveryLog), Failures),
+)]
+#[get("/knapsack/<id>/deliveries")]
+async fn deliveries(id: &str, state: &State<Connections>) -> ApiResult<Json<DeliveryLog>> {
+    if let Ok(id) = Uuid::try_parse(id) {
+        db::get_delivery_log(id, &state.db_conn).map(Json)
+    } else {
+        // ill-formed id
+        Err(ApiError::invalid("id", "Must be a UUID"))
+    }
+}
+
+/// The sensitivity report of the task, if its problem asked for one
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task")),
+    responses((status = 200, description = "The sensitivity report", body = SensitivityReport), Failures),
+)]
+#[get("/knapsack/<id>/sensitivity")]
+async fn sensitivity(id: &str, state: &State<Connections>) -> ApiResult<Json<SensitivityReport>> {
+    if let Ok(id) = Uuid::try_parse(id) {
+        db::get_sensitivity_report(id, &state.db_conn).map(Json)
+    } else {
+        // ill-formed id
+        Err(ApiError::invalid("id", "Must be a UUID"))
+    }
+}
 
-#[get("/knapsack/<id>")]
-async fn status(id: &str, state: &State<Connections>) -> ApiResult<Json<Knapsack>> {
+/// The problem of the task as an LP or MPS model
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task"),
+           ("format" = String, Query, description = "`lp` or `mps`")),
+    responses((status = 200, description = "The model", content_type = "text/plain", body = String), Failures),
+)]
+#[get("/knapsack/<id>/export?<format>")]
+async fn export_problem(id: &str, format: &str, state: &State<Connections>) -> ApiResult<(ContentType, String)> {
+    let format = export::Format::from_str(format)?;
     if let Ok(id) = Uuid::try_parse(id) {
-        db::get(id, &state.db_conn).map(Json)
+        let knapsack = db::get(id, &state.db_conn)?;
+        Ok((ContentType::Plain, export::export(&knapsack, format)))
     } else {
         // ill-formed id
-        Err(Status::new(400))
+        Err(ApiError::invalid("id", "Must be a UUID"))
     }
 }
 
@@ -68,16 +217,152 @@ impl Fairing for Connections {
     }
 }
 
+/// Submits a problem
+#[utoipa::path(
+    tag = "knapsack",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Submits the problem only once for all requests with the key")),
+    request_body = ProblemBody,
+    responses((status = 200, description = "The knapsack of the task", body = Knapsack), Failures),
+)]
 #[post("/knapsack", data = "<problem>")]
-async fn submit(problem: Json<ProblemBody>, state: &State<Connections>) -> ApiResult<Json<Knapsack>> {
-    problem.validate()?;
+async fn submit(problem: Result<Json<ProblemBody>, json::Error<'_>>, key: Result<IdempotencyKey, ApiError>,
+                limits: &State<Limits>, state: &State<Connections>) -> ApiResult<Json<Knapsack>> {
+    let problem = problem.map_err(ApiError::from_json_guard)?;
+    let key = key?;
+    problem.validate(limits)?;
+    let request_hash = idempotency::hash(&problem)?;
     let knapsack = Knapsack::new(problem.0.owned_to_problem());
+    match key.0 {
+        Some(key) => {
+            // A repeated request isn't submitted again, but it's published
+            // again if it hasn't started, in case publishing it failed before
+            if let Some(original) = db::insert_idempotent(&knapsack, &key, &request_hash, &state.db_conn)? {
+                if original.status == dto::Status::Submitted {
+                    queue::publish_problem(&original.task, &state.queue_chann).await?;
+                }
+                return Ok(Json(original));
+            }
+        },
+        None => db::insert(&knapsack, &state.db_conn)?,
+    }
+    queue::publish_problem(&knapsack.task, &state.queue_chann).await?;
+    Ok(Json(knapsack))
+}
+
+/// Cancels a task which hasn't finished
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task"),
+           ("keep_best" = Option<bool>, Query, description = "Whether a running task keeps the best solution found so far")),
+    responses((status = 200, description = "The knapsack of the task", body = Knapsack), Failures),
+)]
+#[delete("/knapsack/<id>?<keep_best>")]
+async fn cancel(id: &str, keep_best: Option<&str>, state: &State<Connections>) -> ApiResult<Json<Knapsack>> {
+    if let Ok(id) = Uuid::try_parse(id) {
+        let keep_best = keep_best
+            .map(|keep_best| keep_best.parse::<bool>()
+                 .map_err(|_| ApiError::invalid("keep_best", "Must be true or false")))
+            .transpose()?;
+        db::cancel(id, keep_best.unwrap_or(false), &state.db_conn).map(Json)
+    } else {
+        // ill-formed id
+        Err(ApiError::invalid("id", "Must be a UUID"))
+    }
+}
+
+/// Submits many problems at once
+#[utoipa::path(
+    tag = "knapsack",
+    request_body = Vec<ProblemBody>,
+    responses((status = 200, description = "The outcome of each problem, in the order they were given", body = Vec<BatchResult>), Failures),
+)]
+#[post("/knapsack/batch", data = "<body>")]
+async fn submit_batch(body: Data<'_>, limits: &State<Limits>, state: &State<Connections>)
+                      -> ApiResult<Json<Vec<BatchResult>>> {
+    let text = body.open(batch::MAX_SIZE_MIB.mebibytes())
+        .into_string()
+        .await
+        .map_err(|e| ApiError::new(Status::BadRequest, "unreadable_body", format!("Could not read the body: {}", e)))?;
+    if !text.is_complete() {
+        return Err(ApiError::new(Status::PayloadTooLarge, "too_large",
+                                 format!("The body is larger than {} MiB", batch::MAX_SIZE_MIB)));
+    }
+    let knapsacks = batch::prepare(&text, limits)?;
+    let submitted: Vec<&Knapsack> = knapsacks.iter().flatten().collect();
+    db::insert_all(submitted.iter().copied(), &state.db_conn)?;
+    let task_ids: Vec<Uuid> = submitted.iter().map(|knapsack| knapsack.task).collect();
+    queue::publish_problems(&task_ids, &state.queue_conn).await?;
+    Ok(Json(knapsacks.into_iter().map(BatchResult::new).collect()))
+}
+
+/// Submits a revision of a task with a solution
+#[utoipa::path(
+    tag = "knapsack",
+    params(("id" = Uuid, Path, description = "The id of the task")),
+    request_body = RevisionBody,
+    responses((status = 200, description = "The knapsack of the task", body = Knapsack), Failures),
+)]
+#[post("/knapsack/<id>/revise", data = "<patch>")]
+async fn revision(id: &str, patch: Result<Json<RevisionBody>, json::Error<'_>>, limits: &State<Limits>,
+                  state: &State<Connections>) -> ApiResult<Json<Knapsack>> {
+    let patch = patch.map_err(ApiError::from_json_guard)?;
+    let Ok(id) = Uuid::try_parse(id) else {
+        // ill-formed id
+        return Err(ApiError::invalid("id", "Must be a UUID"));
+    };
+    let parent = db::get(id, &state.db_conn)?;
+    let knapsack = revise::revise(&parent, patch.0, limits)?;
     db::insert(&knapsack, &state.db_conn)?;
-    queue::publish_problem(&knapsack.task, &state.queue_chann).await;
+    queue::publish_problem(&knapsack.task, &state.queue_chann).await?;
     Ok(Json(knapsack))
 }
 
+/// Checks packed items against a problem
+#[utoipa::path(
+    tag = "knapsack",
+    request_body = VerifyBody,
+    responses((status = 200, description = "Whether the packing is valid", body = Verification), Failures),
+)]
+#[post("/knapsack/verify", data = "<body>")]
+async fn check(body: Result<Json<VerifyBody>, json::Error<'_>>, limits: &State<Limits>)
+               -> ApiResult<Json<Verification>> {
+    let body = body.map_err(ApiError::from_json_guard)?;
+    body.problem.validate(limits).map_err(|e| e.at("/problem"))?;
+    let problem = body.problem.scaled()
+        .ok_or_else(|| ApiError::internal("A valid problem could not be scaled"))?;
+    Ok(Json(verify::verify(&problem, &body.packed_items, body.total_value)))
+}
+
+/// Converts the instances of a file to problems
+#[utoipa::path(
+    tag = "knapsack",
+    params(("format" = String, Query, description = "`or_library` or `pisinger`")),
+    request_body(content = String, content_type = "text/plain"),
+    responses((status = 200, description = "The problems, as request bodies of `POST /knapsack`", body = Vec<ProblemBody>), Failures),
+)]
+#[post("/knapsack/import?<format>", data = "<body>")]
+async fn import_problems(format: &str, body: Data<'_>, limits: &State<Limits>) -> ApiResult<Json<Vec<ProblemBody>>> {
+    let format = import::Format::from_str(format)?;
+    let text = body.open(import::MAX_SIZE_MIB.mebibytes())
+        .into_string()
+        .await
+        .map_err(|e| ApiError::new(Status::BadRequest, "unreadable_body", format!("Could not read the body: {}", e)))?;
+    if !text.is_complete() {
+        return Err(ApiError::new(Status::PayloadTooLarge, "too_large",
+                                 format!("The body is larger than {} MiB", import::MAX_SIZE_MIB)));
+    }
+    let problems = import::import(format, &text, limits)?;
+    Ok(Json(problems.into_iter().map(ProblemBody::Problem).collect()))
+}
+
+fn routes() -> Vec<rocket::Route> {
+    routes![status, submit, check, revision, solutions, sensitivity,
+            export_problem, import_problems, cancel, list_tasks,
+            submit_batch, deliveries, task_events]
+}
+
 #[rocket::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<(), rocket::Error> {
     // Connect to RabbitMQ and Postgres
     let (queue_chann, queue_conn) = queue::init().await;
@@ -86,9 +371,29 @@ async fn main() -> Result<(), rocket::Error> {
     // State to be managed by web framework
     let connections = Connections { queue_chann, queue_conn, db_conn: db_client };
 
-    let _rocket = rocket::build()
+    // Deliveries have a connection of their own, so that they don't hold up
+    // requests. Without a secret to sign them, there are none.
+    let webhook_secret = std::env::var("WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty());
+    if let Some(secret) = webhook_secret.clone() {
+        rocket::tokio::spawn(webhook::run(db::init().await, secret));
+    } else {
+        println!("WEBHOOK_SECRET not set, so problems can't have a callback_url");
+    }
+    let updates = TaskUpdates::listen();
+
+    let rocket = rocket::build();
+    let limits = Limits {
+        callbacks: webhook_secret.is_some(),
+        ..rocket.figment().extract::<Limits>().expect("Invalid limits in the config")
+    };
+
+    let _rocket = rocket
         .manage(connections)
-        .mount("/", routes![status, submit])
+        .manage(updates)
+        .manage(limits)
+        .register("/", catchers![error::catch_default])
+        .mount("/", routes())
+        .mount("/", openapi::docs())
         .launch()
         .await?;
 
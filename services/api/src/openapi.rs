@@ -0,0 +1,93 @@
+//! The OpenAPI document of the api, which is generated from the routes and
+//! the types they take and return. It's served at `/openapi.json`, along
+//! with interactive docs at `/docs`.
+//!
+//! `openapi.json` in the root of the crate is the document as of the last
+//! change, which the tests keep in sync with the code. Run them with
+//! `UPDATE_OPENAPI=1` to update it.
+
+use std::collections::BTreeMap;
+
+use utoipa::openapi::response::{Response, ResponseBuilder};
+use utoipa::openapi::{Content, Ref, RefOr};
+use utoipa::{IntoResponses, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::error::ApiError;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Knapsack service",
+        description = "Solves knapsack problems asynchronously: a problem is submitted as a task, \
+                       which the optimizer solves, and whose knapsack is then read back.",
+    ),
+    paths(
+        crate::status, crate::list_tasks, crate::task_events, crate::solutions,
+        crate::deliveries, crate::sensitivity, crate::export_problem, crate::submit,
+        crate::cancel, crate::submit_batch, crate::revision, crate::check,
+        crate::import_problems,
+    ),
+    components(schemas(crate::events::ProgressEvent)),
+)]
+pub struct ApiDoc;
+
+/// The errors any route may fail with, see `error`
+pub struct Failures;
+
+impl IntoResponses for Failures {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        let schema = Ref::from_schema_name(ApiError::name());
+        [("4XX", "The request is invalid, or conflicts with the state of the task"),
+         ("5XX", "The request failed, and may be retried if the status is 503")]
+            .into_iter()
+            .map(|(status, description)| {
+                let response = ResponseBuilder::new()
+                    .description(description)
+                    .content("application/problem+json", Content::new(Some(schema.clone())))
+                    .build();
+                (status.to_string(), response.into())
+            })
+            .collect()
+    }
+}
+
+/// The routes serving the document and the docs
+pub fn docs() -> SwaggerUi {
+    SwaggerUi::new("/docs/<_..>").url("/openapi.json", ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Method;
+
+    use super::*;
+
+    #[test]
+    fn document_is_up_to_date() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+        let generated = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(path, &generated).unwrap();
+        }
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(committed == generated, "openapi.json is out of date, run the tests with UPDATE_OPENAPI=1");
+    }
+
+    #[test]
+    fn documents_every_route() {
+        let document = ApiDoc::openapi();
+        for route in crate::routes() {
+            let path = route.uri.path().replace('<', "{").replace('>', "}");
+            let item = document.paths.paths.get(&path)
+                .unwrap_or_else(|| panic!("{} is not documented", path));
+            let operation = match route.method {
+                Method::Get => &item.get,
+                Method::Post => &item.post,
+                Method::Delete => &item.delete,
+                method => panic!("{} {} is not documented", method, path),
+            };
+            assert!(operation.is_some(), "{} {} is not documented", route.method, path);
+        }
+    }
+}
@@ -6,7 +6,9 @@ use lapin::ConnectionProperties;
 use backoff::ExponentialBackoff;
 use backoff::future::retry;
 
-use crate::get_var;
+use rocket::http::Status;
+
+use crate::{get_var, ApiError, ApiResult};
 
 const AMQP_EXCHANGE: &str = "";
 const AMQP_QUEUE_NAME: &str = "problem_submitted";
@@ -37,7 +39,9 @@ pub async fn init() -> (Channel, Connection) {
     (chan, conn)
 }
 
-pub async fn publish_problem<'a>(task_id: &Uuid, chann: &Channel) {
+/// Publishes the task to the optimizer, failing with 503 if the broker can't be
+/// reached. The task is stored by then, but never started.
+pub async fn publish_problem(task_id: &Uuid, chann: &Channel) -> ApiResult<()> {
     use lapin::BasicProperties;
     use lapin::options::BasicPublishOptions;
 
@@ -51,7 +55,61 @@ pub async fn publish_problem<'a>(task_id: &Uuid, chann: &Channel) {
             BasicPublishOptions::default(),
             payload,
             BasicProperties::default(),
-        ).await.map_err(to_backoff_err)}).await.unwrap();
+        ).await.map_err(to_backoff_err)}).await
+        .map_err(|e| {
+            eprintln!("Could not publish task {}: {}", task_id, e);
+            ApiError::new(Status::ServiceUnavailable, "queue_unavailable",
+                          format!("Task {} was stored, but could not be queued", task_id))
+        })?;
 
     // todo: producer confirms currently not requested
+    Ok(())
+}
+
+/// Publishes the tasks on a channel of their own, on which the broker confirms
+/// each of them, so that they're sent without waiting for one another but are
+/// known to be queued once this returns. Like a single task, they're stored
+/// but never started if the broker can't be reached.
+pub async fn publish_problems(task_ids: &[Uuid], conn: &Connection) -> ApiResult<()> {
+    retry(ExponentialBackoff::default(), || async {
+        let chann = conn.create_channel().await.map_err(to_backoff_err)?;
+        let published = publish_confirmed(task_ids, &chann).await;
+        if let Err(e) = chann.close(200, "published batch").await {
+            eprintln!("Could not close channel of batch: {}", e);
+        }
+        published
+    }).await
+        .map_err(|e| {
+            eprintln!("Could not publish {} tasks: {}", task_ids.len(), e);
+            ApiError::new(Status::ServiceUnavailable, "queue_unavailable",
+                          format!("{} tasks were stored, but could not be queued", task_ids.len()))
+        })
+}
+
+/// Publishes the tasks, and then waits for the broker to confirm all of them.
+/// Tasks published again when this is retried are skipped by the optimizer
+/// once they're started.
+async fn publish_confirmed(task_ids: &[Uuid], chann: &Channel) -> Result<(), backoff::Error<String>> {
+    use lapin::BasicProperties;
+    use lapin::options::{BasicPublishOptions, ConfirmSelectOptions};
+
+    chann.confirm_select(ConfirmSelectOptions::default()).await.map_err(to_backoff_err)?;
+    let mut confirms = Vec::with_capacity(task_ids.len());
+    for task_id in task_ids {
+        let confirm = chann.basic_publish(
+            AMQP_EXCHANGE,
+            AMQP_QUEUE_NAME,
+            BasicPublishOptions::default(),
+            task_id.to_string().as_bytes(),
+            BasicProperties::default(),
+        ).await.map_err(to_backoff_err)?;
+        confirms.push(confirm);
+    }
+
+    let confirmations = futures::future::try_join_all(confirms).await.map_err(to_backoff_err)?;
+    let rejected = confirmations.iter().filter(|c| c.is_nack()).count();
+    if rejected > 0 {
+        return Err(backoff::Error::transient(format!("The broker rejected {} tasks", rejected)));
+    }
+    Ok(())
 }
@@ -0,0 +1,339 @@
+//! Revisions of tasks with a solution, where a few items are added or removed,
+//! or the capacity is changed. A revision is a new task linked to its parent,
+//! which starts from the solution of the parent instead of from scratch.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::decimal::Decimal;
+use crate::dto::{Algorithm, Constraint, Knapsack, MyOption, Problem, ScaledProblem, Solution, Status};
+use crate::validation::Limits;
+use crate::{ApiError, ApiResult};
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct RevisionBody {
+    // the new capacity, if it changes
+    #[serde(default)]
+    pub capacity: Option<Decimal>,
+    // indices of the items to remove
+    #[serde(default)]
+    pub remove: Vec<u32>,
+    // items to add after the remaining ones
+    #[serde(default)]
+    pub add: Vec<NewItem>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct NewItem {
+    pub weight: Decimal,
+    pub value: Decimal,
+    // only for robust problems, where it defaults to zero
+    #[serde(default)]
+    pub deviation: Option<Decimal>,
+    #[serde(default)]
+    pub category: Option<String>,
+    // coefficients in the linear constraints, which default to zero
+    #[serde(default)]
+    pub coefficients: Option<Vec<f64>>,
+}
+
+/// The task a revision was made from
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Revision {
+    pub parent: Uuid,
+    // indices of the items of the parent which were removed, in increasing
+    // order. The remaining items keep their order, and the added items
+    // come after them.
+    pub removed_items: Vec<u32>,
+    // scaled upper bound on the optimal value, known from the parent
+    #[serde(skip)]
+    pub inherited_bound: Option<u64>,
+}
+
+/// How a solution differs from the solution of the parent task
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Changes {
+    pub parent_value: Decimal,
+    // indices of the items which are packed, but weren't by the parent
+    pub packed: Vec<u32>,
+    // indices of the items of the parent which it packed, but which
+    // aren't packed anymore
+    pub unpacked: Vec<u32>,
+}
+
+impl Revision {
+    /// The index of item `i` of the parent, unless it was removed
+    fn index(&self, i: u32) -> Option<u32> {
+        if self.removed_items.contains(&i) {
+            None
+        } else {
+            Some(i - self.removed_items.iter().filter(|r| **r < i).count() as u32)
+        }
+    }
+
+    pub fn changes(&self, parent: &Solution, solution: &Solution) -> Changes {
+        let kept: HashSet<u32> = parent.packed_items.iter()
+            .filter_map(|i| self.index(*i))
+            .collect();
+        Changes {
+            parent_value: parent.total_value,
+            packed: solution.packed_items.iter()
+                .copied()
+                .filter(|i| !kept.contains(i))
+                .collect(),
+            unpacked: parent.packed_items.iter()
+                .copied()
+                .filter(|i| !self.index(*i).is_some_and(|i| solution.packed_items.contains(&i)))
+                .collect(),
+        }
+    }
+}
+
+/// Applies the patch to the problem of the parent, which must have a
+/// solution, i.e. have completed or have been cancelled keeping its best
+/// packing. The solution of the parent, without the removed items, is the
+/// initial solution of the revision.
+pub fn revise(parent: &Knapsack, patch: RevisionBody, limits: &Limits) -> ApiResult<Knapsack> {
+    let MyOption::Some(solution) = &parent.solution else {
+        return Err(ApiError::new(rocket::http::Status::Conflict, "task_unsolved",
+                                 "Only tasks with a solution can be revised"));
+    };
+
+    let removed: HashSet<u32> = patch.remove.iter().copied().collect();
+    if removed.len() != patch.remove.len()
+        || removed.iter().any(|i| *i as usize >= parent.problem.weights.len())
+    {
+        return Err(ApiError::invalid("/remove", "Must be distinct indices of items"));
+    }
+    let mut removed_items = patch.remove.clone();
+    removed_items.sort();
+    let revision = Revision { parent: parent.task, removed_items, inherited_bound: None };
+
+    let mut weights = remaining(&parent.problem.weights, &removed);
+    let mut values = remaining(&parent.problem.values, &removed);
+    weights.extend(patch.add.iter().map(|item| item.weight));
+    values.extend(patch.add.iter().map(|item| item.value));
+
+    let deviations = match &parent.problem.deviations {
+        Some(deviations) => {
+            let mut deviations = remaining(deviations, &removed);
+            deviations.extend(patch.add.iter()
+                .map(|item| item.deviation.unwrap_or(Decimal::from(0))));
+            Some(deviations)
+        },
+        None if patch.add.iter().any(|item| item.deviation.is_some()) => {
+            return Err(ApiError::invalid("/add", "Items of a problem which isn't robust have no deviation"));
+        },
+        None => None,
+    };
+
+    // Items only need to be labelled if some of them have a category
+    let categories = if parent.problem.categories.is_some()
+        || patch.add.iter().any(|item| item.category.is_some())
+    {
+        let unlabelled = vec![None; parent.problem.weights.len()];
+        let categories = parent.problem.categories.as_ref().unwrap_or(&unlabelled);
+        let mut categories = remaining(categories, &removed);
+        categories.extend(patch.add.iter().map(|item| item.category.clone()));
+        Some(categories)
+    } else {
+        None
+    };
+
+    // Each added item needs a coefficient in each constraint
+    let constraints = match &parent.problem.constraints {
+        Some(constraints) => {
+            if let Some(i) = patch.add.iter()
+                .position(|item| item.coefficients.as_ref().is_some_and(|c| c.len() != constraints.len()))
+            {
+                return Err(ApiError::invalid(format!("/add/{}/coefficients", i),
+                                             "Must have a coefficient for each constraint"));
+            }
+            Some(constraints.iter()
+                .enumerate()
+                .map(|(j, constraint)| {
+                    let mut coefficients = remaining(&constraint.coefficients, &removed);
+                    coefficients.extend(patch.add.iter()
+                        .map(|item| item.coefficients.as_ref().map_or(0.0, |c| c[j])));
+                    Constraint { coefficients, ..constraint.clone() }
+                })
+                .collect())
+        },
+        None if patch.add.iter().any(|item| item.coefficients.is_some()) => {
+            return Err(ApiError::invalid("/add", "Items of a problem without constraints have no coefficients"));
+        },
+        None => None,
+    };
+
+    let problem = Problem {
+        capacity: patch.capacity.unwrap_or(parent.problem.capacity),
+        weights,
+        values,
+        deviations,
+        categories,
+        constraints,
+        // The numbers of the parent are scaled by its precision, so the
+        // precision of the revision is at least as large
+        precision: None,
+        ..parent.problem.clone()
+    };
+    // The revised problem is validated before it's scaled, and again once it
+    // has its initial solution
+    problem.validate(limits)?;
+    let scaled = problem.scaled()
+        .ok_or_else(|| ApiError::internal("A valid problem could not be scaled"))?;
+
+    let initial_solution = repair(&scaled, solution.packed_items.iter()
+        .filter_map(|i| revision.index(*i))
+        .collect());
+    let inherited_bound = inherited_bound(parent, solution, &problem, &patch);
+
+    let problem = Problem { initial_solution, ..problem };
+    problem.validate(limits)?;
+
+    Ok(Knapsack {
+        revision: Some(Revision { inherited_bound, ..revision }),
+        ..Knapsack::new(problem)
+    })
+}
+
+/// The items which weren't removed, in their original order
+fn remaining<T: Clone>(items: &[T], removed: &HashSet<u32>) -> Vec<T> {
+    items.iter()
+        .enumerate()
+        .filter(|(i, _)| !removed.contains(&(*i as u32)))
+        .map(|(_, item)| item.clone())
+        .collect()
+}
+
+/// Leaves out the packed items of least value per unit of weight until the
+/// packing fits into the knapsack (in the worst case) without exceeding any
+/// limit or violating any constraint, which is only needed if the capacity
+/// or a limit shrank, or an item with a negative coefficient was removed.
+/// Constraints with negative coefficients or bounds may not be satisfiable
+/// that way, in which case there's no initial solution.
+fn repair(problem: &ScaledProblem, mut packed_items: Vec<u32>) -> Option<Vec<u32>> {
+    let weight = |i: &u32| u128::from(problem.weights[*i as usize]);
+    let value = |i: &u32| u128::from(problem.values[*i as usize]);
+    packed_items.sort_by(|a, b| (value(b) * weight(a)).cmp(&(value(a) * weight(b))));
+
+    let feasible = |packed_items: &[u32]| {
+        problem.worst_case_weight(packed_items) <= u128::from(problem.capacity)
+            && problem.limit_violations(packed_items).is_empty()
+            && problem.constraint_violations(packed_items).is_empty()
+    };
+    while !feasible(&packed_items) {
+        packed_items.pop()?;
+    }
+    packed_items.sort();
+    Some(packed_items)
+}
+
+/// Removing items or shrinking the capacity can't increase the optimum, so
+/// if the parent's optimum is bounded, the revision's optimum is bounded by
+/// that plus the values of the added items
+fn inherited_bound(parent: &Knapsack, solution: &Solution, problem: &Problem,
+                   patch: &RevisionBody) -> Option<u64> {
+    let parent_bound = match parent.problem.algorithm {
+        // The solution of a cancelled exact search needn't be optimal
+        Some(Algorithm::BranchAndBound | Algorithm::Milp)
+            if matches!(parent.status, Status::Completed) => solution.total_value,
+        Some(Algorithm::Fptas) => solution.guarantee.as_ref()?.upper_bound,
+        _ => return None,
+    };
+
+    let precision = problem.precision();
+    let capacity = |n: &Decimal| n.rescale(precision.weights);
+    if capacity(&problem.capacity)? > capacity(&parent.problem.capacity)? {
+        return None;
+    }
+
+    let value = |n: &Decimal| n.rescale(precision.values);
+    let bound = patch.add.iter()
+        .try_fold(value(&parent_bound)?, |bound, item| bound.checked_add(value(&item.value)?))?;
+    i64::try_from(bound).ok().map(|bound| bound as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::Guarantee;
+
+    fn decimal(n: &str) -> Decimal {
+        Decimal::parse(n).unwrap()
+    }
+
+    /// A completed parent solved by `algorithm`, which packed the first two
+    /// of its three items for a total value of 7.5
+    fn parent(algorithm: Algorithm, upper_bound: Option<&str>) -> Knapsack {
+        let problem = Problem {
+            algorithm: Some(algorithm),
+            epsilon: (algorithm == Algorithm::Fptas).then_some(0.1),
+            ..Problem::new(decimal("10"), ["4", "5", "6"].map(decimal).to_vec(),
+                           ["3.5", "4", "2"].map(decimal).to_vec())
+        };
+        let solution = Solution {
+            packed_items: vec![0, 1],
+            total_value: decimal("7.5"),
+            total_weight: decimal("9"),
+            guarantee: upper_bound.map(|b| Guarantee { epsilon: 0.1, upper_bound: decimal(b) }),
+            warm_start: None,
+            worst_case_weight: None,
+            changes: None,
+        };
+        Knapsack { status: Status::Completed, solution: MyOption::Some(solution), ..Knapsack::new(problem) }
+    }
+
+    fn patch(capacity: Option<&str>, add: &[(&str, &str)]) -> RevisionBody {
+        RevisionBody {
+            capacity: capacity.map(decimal),
+            remove: vec![2],
+            add: add.iter()
+                .map(|(weight, value)| NewItem {
+                    weight: decimal(weight),
+                    value: decimal(value),
+                    deviation: None,
+                    category: None,
+                    coefficients: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn bound(parent: &Knapsack, patch: RevisionBody) -> Option<u64> {
+        revise(parent, patch, &Limits::default()).unwrap().revision.unwrap().inherited_bound
+    }
+
+    #[test]
+    fn inherits_the_optimum_of_an_exact_parent() {
+        for algorithm in [Algorithm::BranchAndBound, Algorithm::Milp] {
+            let parent = parent(algorithm, None);
+            // Scaled by the precision of the values, which is one decimal
+            assert_eq!(bound(&parent, patch(None, &[])), Some(75));
+            assert_eq!(bound(&parent, patch(Some("9"), &[])), Some(75));
+            // The added values are scaled by their precision too
+            assert_eq!(bound(&parent, patch(None, &[("1", "1.25")])), Some(875));
+        }
+    }
+
+    #[test]
+    fn inherits_the_guarantee_of_an_approximate_parent() {
+        assert_eq!(bound(&parent(Algorithm::Fptas, Some("8")), patch(None, &[("1", "2")])), Some(100));
+        assert_eq!(bound(&parent(Algorithm::Fptas, None), patch(None, &[])), None);
+    }
+
+    #[test]
+    fn inherits_nothing_without_a_proven_bound() {
+        assert_eq!(bound(&parent(Algorithm::Genetic, None), patch(None, &[])), None);
+
+        // A larger capacity may allow for a better packing
+        let exact = parent(Algorithm::BranchAndBound, None);
+        assert_eq!(bound(&exact, patch(Some("10.5"), &[])), None);
+
+        // A cancelled search keeps a packing which needn't be optimal
+        let cancelled = Knapsack { status: Status::Cancelled, ..exact };
+        assert_eq!(bound(&cancelled, patch(None, &[])), None);
+    }
+}
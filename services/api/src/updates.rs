@@ -0,0 +1,202 @@
+//! Wakes the requests which wait for a task to change, see
+//! `GET /knapsack/<id>?wait=` and `GET /knapsack/<id>/events`. Whoever
+//! changes the status of a task, the optimizer or the api, notifies its id on
+//! the Postgres channel `CHANNEL` in the same transaction, and the optimizer
+//! notifies the progress of its solvers on `PROGRESS_CHANNEL`. A thread
+//! listens for the notifications on a connection of its own, and broadcasts
+//! them to the waiting requests, which then read the task again. Waiting
+//! requests hold neither connections nor the lock of the shared one.
+
+use std::thread;
+use std::time::Duration;
+
+use diesel::pg::PgConnection;
+use diesel::RunQueryDsl;
+use rocket::tokio::sync::broadcast::{self, error::RecvError, Receiver};
+use rocket::tokio::time::{timeout_at, Instant};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::db;
+use crate::dto::{Knapsack, Status};
+use crate::ApiResult;
+
+pub const CHANNEL: &str = "task_updates";
+pub const PROGRESS_CHANNEL: &str = "task_progress";
+
+/// Longest wait of a request, in seconds
+pub const MAX_WAIT: u64 = 60;
+
+/// How often the listening connection is checked for notifications, which
+/// only reads those Postgres has already sent, without querying it
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Number of updates a waiting request may fall behind by, after which it
+/// reads its task again
+const CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Update {
+    // the id of the task whose status changed
+    Status(Uuid),
+    Progress(Progress),
+}
+
+/// How far the solver of a running task has come, as the optimizer reports it
+#[derive(Deserialize, Clone, Debug)]
+pub struct Progress {
+    pub task: Uuid,
+    pub generation: u64,
+    // scaled like the values of the task
+    pub best_value: Option<i64>,
+    // seconds since the solver started
+    pub elapsed: f64,
+}
+
+pub struct TaskUpdates(broadcast::Sender<Update>);
+
+impl TaskUpdates {
+    /// Starts listening for updates
+    pub fn listen() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let updates = sender.clone();
+        thread::spawn(move || loop {
+            // Waiting requests may miss updates until it's reconnected, and
+            // then time out
+            if let Err(e) = forward(&mut db::connect(), &updates) {
+                eprintln!("Lost connection listening for task updates: {}", e);
+            }
+        });
+        TaskUpdates(sender)
+    }
+
+    pub fn subscribe(&self) -> Receiver<Update> {
+        self.0.subscribe()
+    }
+}
+
+/// Forwards the notifications on the connection, until it fails
+fn forward(conn: &mut PgConnection, updates: &broadcast::Sender<Update>) -> diesel::QueryResult<()> {
+    diesel::sql_query(format!("LISTEN {}", CHANNEL)).execute(conn)?;
+    diesel::sql_query(format!("LISTEN {}", PROGRESS_CHANNEL)).execute(conn)?;
+    loop {
+        for notification in conn.notifications_iter() {
+            let notification = notification?;
+            if let Some(update) = parse(&notification.channel, &notification.payload) {
+                // Fails only if no request is waiting
+                let _ = updates.send(update);
+            }
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+/// The update notified on the channel, unless its payload is malformed
+fn parse(channel: &str, payload: &str) -> Option<Update> {
+    match channel {
+        CHANNEL => Uuid::try_parse(payload).ok().map(Update::Status),
+        _ => serde_json::from_str(payload).ok().map(Update::Progress),
+    }
+}
+
+/// Reads the task once it has reached the status `until`, or after waiting
+/// for `wait` seconds, whichever comes first
+pub async fn wait(task_id: Uuid, until: Status, wait: u64, updates: &TaskUpdates, conn: &db::Connection)
+                  -> ApiResult<Knapsack> {
+    let deadline = Instant::now() + Duration::from_secs(wait);
+    // Subscribed before the task is read, so that no update is missed
+    let mut receiver = updates.subscribe();
+    loop {
+        let knapsack = db::get(task_id, conn)?;
+        if knapsack.status.reached(&until) || !changed(task_id, deadline, &mut receiver).await {
+            return Ok(knapsack);
+        }
+    }
+}
+
+/// Waits until the status of the task may have changed, or returns `false`
+/// once the deadline has passed without it changing
+async fn changed(task_id: Uuid, deadline: Instant, receiver: &mut Receiver<Update>) -> bool {
+    loop {
+        match timeout_at(deadline, receiver.recv()).await {
+            Ok(Ok(Update::Status(id))) if id == task_id => return true,
+            Ok(Ok(_)) => continue,
+            // Some updates were missed, which may have been of the task
+            Ok(Err(RecvError::Lagged(_))) => return true,
+            Ok(Err(RecvError::Closed)) | Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK: Uuid = uuid::uuid!("2881a781-9c3d-4eba-b450-290d98c68026");
+    const OTHER: Uuid = uuid::uuid!("f3c7a0c2-5d1e-4a8b-9b7e-0d9f3a6c1e24");
+
+    fn progress(task: Uuid) -> Update {
+        Update::Progress(Progress { task, generation: 1, best_value: None, elapsed: 0.5 })
+    }
+
+    #[test]
+    fn parses_the_notifications_of_both_channels() {
+        assert!(matches!(parse(CHANNEL, &TASK.to_string()), Some(Update::Status(id)) if id == TASK));
+        let payload = format!(r#"{{"task": "{}", "generation": 3, "best_value": 42, "elapsed": 1.5}}"#, TASK);
+        assert!(matches!(parse(PROGRESS_CHANNEL, &payload),
+                         Some(Update::Progress(Progress { task, generation: 3, best_value: Some(42), .. }))
+                         if task == TASK));
+        assert!(parse(CHANNEL, "not a task").is_none());
+        assert!(parse(PROGRESS_CHANNEL, r#"{"task": "not a task"}"#).is_none());
+    }
+
+    #[test]
+    fn waits_until_the_status_to_wait_for_is_reached() {
+        assert!(!Status::Submitted.reached(&Status::Started));
+        assert!(Status::Started.reached(&Status::Started));
+        assert!(!Status::Started.reached(&Status::Completed));
+        // A task which finished otherwise will never complete
+        assert!(Status::Failed.reached(&Status::Completed));
+        assert!(Status::Cancelled.reached(&Status::Completed));
+    }
+
+    #[rocket::async_test]
+    async fn wakes_on_a_change_of_the_task() {
+        let (sender, mut receiver) = broadcast::channel(CAPACITY);
+        for update in [Update::Status(OTHER), progress(TASK), Update::Status(TASK)] {
+            sender.send(update).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(MAX_WAIT);
+        assert!(changed(TASK, deadline, &mut receiver).await);
+    }
+
+    #[rocket::async_test]
+    async fn times_out_at_the_deadline() {
+        let (sender, mut receiver) = broadcast::channel(CAPACITY);
+        sender.send(Update::Status(OTHER)).unwrap();
+        sender.send(progress(TASK)).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert!(!changed(TASK, deadline, &mut receiver).await);
+        assert!(Instant::now() >= deadline);
+
+        // Waiting for no time at all returns at once
+        assert!(!changed(TASK, Instant::now(), &mut receiver).await);
+    }
+
+    #[rocket::async_test]
+    async fn reads_again_after_missing_updates() {
+        let (sender, mut receiver) = broadcast::channel(1);
+        sender.send(Update::Status(OTHER)).unwrap();
+        sender.send(Update::Status(OTHER)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(MAX_WAIT);
+        assert!(changed(TASK, deadline, &mut receiver).await);
+    }
+
+    #[rocket::async_test]
+    async fn stops_once_nothing_is_listened_for() {
+        let (sender, mut receiver) = broadcast::channel::<Update>(CAPACITY);
+        drop(sender);
+        let deadline = Instant::now() + Duration::from_secs(MAX_WAIT);
+        assert!(!changed(TASK, deadline, &mut receiver).await);
+    }
+}
@@ -0,0 +1,293 @@
+//! Validates problems before they're submitted, reporting all violations at
+//! once, each as a JSON pointer into the problem and what's wrong with it.
+//!
+//! The size of a problem is bounded by `Limits`, which are read from the
+//! config of Rocket, e.g. `ROCKET_MAX_ITEMS=1000` in the environment.
+
+use serde::Deserialize;
+
+use crate::decimal::{Decimal, MAX_SCALE};
+use crate::dto::{Algorithm, Problem, MAX_ALTERNATIVES, MAX_LABEL_LENGTH, MAX_TAGS};
+use crate::error::FieldError;
+use crate::webhook;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Limits {
+    // number of items of a problem
+    pub max_items: usize,
+    // capacity of a problem, in whole units
+    pub max_capacity: u64,
+    // whether problems may have a `callback_url`, which they may not unless
+    // the api can sign the deliveries, see `webhook`
+    #[serde(skip)]
+    pub callbacks: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_items: 100_000, max_capacity: 1_000_000_000_000, callbacks: true }
+    }
+}
+
+/// The violations found so far
+#[derive(Default)]
+struct Violations(Vec<FieldError>);
+
+impl Violations {
+    fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError { field: field.into(), message: message.into() });
+    }
+
+    fn check(&mut self, valid: bool, field: impl Into<String>, message: impl Into<String>) {
+        if !valid {
+            self.add(field, message);
+        }
+    }
+}
+
+/// All violations of the problem, which is valid if there are none. Checks
+/// which index the items, or need the problem scaled, are only done once the
+/// rest of the problem is valid.
+pub fn violations(problem: &Problem, limits: &Limits) -> Vec<FieldError> {
+    let mut violations = Violations::default();
+    check_items(problem, limits, &mut violations);
+    check_options(problem, limits, &mut violations);
+    check_labels(problem, &mut violations);
+    if violations.0.is_empty() {
+        check_scaled(problem, &mut violations);
+    }
+    violations.0
+}
+
+/// The items must be given in full, and within the limits
+fn check_items(problem: &Problem, limits: &Limits, violations: &mut Violations) {
+    let n = problem.weights.len();
+    violations.check(n > 0, "/weights", "Must have at least one item");
+    violations.check(n <= limits.max_items, "/weights",
+                     format!("Must have at most {} items", limits.max_items));
+    violations.check(problem.values.len() == n, "/values", "Must have a value for each weight");
+
+    // Compared in the scale of the capacity, which is larger than the limit
+    // unless it can't be represented
+    let within_limit = match Decimal::from(limits.max_capacity).rescale(problem.capacity.scale) {
+        Some(max_capacity) => problem.capacity.units <= max_capacity,
+        None => true,
+    };
+    violations.check(within_limit, "/capacity", format!("Must be at most {}", limits.max_capacity));
+
+    if let Some(deviations) = &problem.deviations {
+        violations.check(deviations.len() == n, "/deviations", "Must have a deviation for each item");
+    }
+    if let Some(categories) = &problem.categories {
+        violations.check(categories.len() == n, "/categories",
+                         "Must have a category, or null, for each item");
+    }
+    for (j, constraint) in problem.constraints.iter().flatten().enumerate() {
+        let coefficients = format!("/constraints/{}/coefficients", j);
+        violations.check(constraint.coefficients.len() == n, &coefficients,
+                         "Must have a coefficient for each item");
+        violations.check(constraint.coefficients.iter().all(|a| a.is_finite()), coefficients,
+                         "Must be finite");
+        violations.check(constraint.bound.is_finite(), format!("/constraints/{}/bound", j),
+                         "Must be finite");
+    }
+}
+
+/// The options must go together, and with the algorithm
+fn check_options(problem: &Problem, limits: &Limits, violations: &mut Violations) {
+    let exact = problem.epsilon.is_none() && problem.algorithm != Some(Algorithm::Fptas);
+
+    // A robust problem needs a deviation for each item, and can't be
+    // approximated or analysed
+    match (&problem.deviations, problem.gamma) {
+        (Some(_), Some(gamma)) => {
+            violations.check(gamma as usize <= problem.weights.len(), "/gamma",
+                             "Must be at most the number of items");
+            violations.check(exact && !problem.sensitivity, "/deviations",
+                             "Robust problems can't be approximated or analysed");
+        },
+        (Some(_), None) => violations.add("/gamma", "Must be given along with deviations"),
+        (None, Some(_)) => violations.add("/deviations", "Must be given along with gamma"),
+        (None, None) => {},
+    }
+
+    // Limits on the number of items must fit into the db, and can't be
+    // approximated or analysed either
+    if problem.category_limits.is_some() && problem.categories.is_none() {
+        violations.add("/categories", "Must be given along with category limits");
+    }
+    if let Some(max_items) = problem.max_items {
+        violations.check(i32::try_from(max_items).is_ok(), "/max_items",
+                         format!("Must be at most {}", i32::MAX));
+    }
+    for (name, limit) in problem.category_limits.iter().flatten() {
+        violations.check(i32::try_from(*limit).is_ok(),
+                         format!("/category_limits/{}", pointer_token(name)),
+                         format!("Must be at most {}", i32::MAX));
+    }
+    if !exact || problem.sensitivity {
+        let message = "Limited problems can't be approximated or analysed";
+        violations.check(problem.max_items.is_none(), "/max_items", message);
+        violations.check(problem.category_limits.is_none(), "/category_limits", message);
+    }
+
+    // Linear constraints are only supported by the milp algorithm
+    if problem.constraints.is_some() {
+        violations.check(matches!(problem.algorithm, None | Some(Algorithm::Milp)), "/algorithm",
+                         "Constraints are only supported by milp");
+        let message = "Not supported with constraints";
+        violations.check(problem.epsilon.is_none(), "/epsilon", message);
+        violations.check(problem.alternatives.is_none(), "/alternatives", message);
+        violations.check(problem.tie_break.is_none(), "/tie_break", message);
+        violations.check(!problem.sensitivity, "/sensitivity", message);
+    }
+
+    if let Some(alternatives) = problem.alternatives {
+        violations.check((1..=MAX_ALTERNATIVES).contains(&alternatives.count), "/alternatives/count",
+                         format!("Must be 1 to {}", MAX_ALTERNATIVES));
+    }
+
+    // Only the exact search can rank the solutions and break ties
+    let branch_and_bound = problem.epsilon.is_none()
+        && matches!(problem.algorithm, None | Some(Algorithm::BranchAndBound));
+    if !branch_and_bound {
+        let message = "Only supported by branch_and_bound";
+        violations.check(problem.alternatives.is_none(), "/alternatives", message);
+        violations.check(problem.tie_break.is_none(), "/tie_break", message);
+    }
+
+    // The approximation ratio must be given for, and only for, the fptas
+    match (problem.algorithm, problem.epsilon) {
+        (Some(Algorithm::Genetic | Algorithm::Milp), Some(_)) => {
+            violations.add("/epsilon", "Only supported by fptas");
+        },
+        (Some(Algorithm::Fptas), None) => violations.add("/epsilon", "Must be given for fptas"),
+        (_, Some(epsilon)) if !(epsilon > 0.0 && epsilon < 1.0) => {
+            violations.add("/epsilon", "Must be between 0 and 1, exclusive");
+        },
+        _ => {},
+    }
+
+    let precision = problem.precision();
+    let message = format!("Must be at most {}", MAX_SCALE);
+    violations.check(precision.weights <= MAX_SCALE, "/precision/weights", &message);
+    violations.check(precision.values <= MAX_SCALE, "/precision/values", message);
+
+    if let Some(url) = &problem.callback_url {
+        if limits.callbacks {
+            violations.check(webhook::is_valid_url(url), "/callback_url", format!(
+                "Must be a public http(s) url of at most {} characters", webhook::MAX_URL_LENGTH));
+        } else {
+            violations.add("/callback_url", "Webhooks are disabled on this server");
+        }
+    }
+}
+
+/// Owners and tags are short, non-empty labels
+fn check_labels(problem: &Problem, violations: &mut Violations) {
+    let is_label = |l: &String| !l.is_empty() && l.chars().count() <= MAX_LABEL_LENGTH;
+    let message = format!("Must have 1 to {} characters", MAX_LABEL_LENGTH);
+    if let Some(owner) = &problem.owner {
+        violations.check(is_label(owner), "/owner", &message);
+    }
+    if let Some(tags) = &problem.tags {
+        violations.check(tags.len() <= MAX_TAGS, "/tags", format!("Must have at most {} tags", MAX_TAGS));
+        for (i, tag) in tags.iter().enumerate() {
+            violations.check(is_label(tag), format!("/tags/{}", i), &message);
+        }
+    }
+}
+
+/// Each number must have at most as many decimals as its precision, and fit
+/// into the db once scaled, see `Problem::scaled`, as must the total value,
+/// which the solvers sum up. An initial solution must then be feasible.
+fn check_scaled(problem: &Problem, violations: &mut Violations) {
+    let precision = problem.precision();
+    let fits = |n: &Decimal, scale: u32| n.rescale(scale).is_some_and(|n| i64::try_from(n).is_ok());
+    let out_of_range = |scale: u32| {
+        format!("Must have at most {} decimals, and be less than 2^63 once scaled by 10^{}", scale, scale)
+    };
+
+    violations.check(fits(&problem.capacity, precision.weights), "/capacity", out_of_range(precision.weights));
+    let numbers = [("weights", &problem.weights, precision.weights),
+                   ("values", &problem.values, precision.values)]
+        .into_iter()
+        .chain(problem.deviations.as_ref().map(|d| ("deviations", d, precision.weights)));
+    for (field, numbers, scale) in numbers {
+        for (i, n) in numbers.iter().enumerate() {
+            violations.check(fits(n, scale), format!("/{}/{}", field, i), out_of_range(scale));
+        }
+    }
+
+    let Some(scaled) = problem.scaled() else {
+        return;
+    };
+    let total_value = scaled.values.iter()
+        .try_fold(0i64, |acc, v| acc.checked_add(*v as i64));
+    violations.check(total_value.is_some(), "/values", "Must sum to less than 2^63 once scaled");
+
+    if let Some(initial_solution) = &problem.initial_solution {
+        let verification = crate::verify::verify(&scaled, initial_solution, None);
+        violations.check(verification.valid, "/initial_solution", verification.violations.join(", "));
+    }
+}
+
+/// Escapes a key of an object for a JSON pointer, see RFC 6901
+fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn problem(capacity: &str, weights: &[&str], values: &[&str]) -> Problem {
+        let decimals = |numbers: &[&str]| numbers.iter().map(|n| Decimal::parse(n).unwrap()).collect();
+        Problem::new(Decimal::parse(capacity).unwrap(), decimals(weights), decimals(values))
+    }
+
+    /// The pointers of the violations, in the order they're reported
+    fn pointers(problem: &Problem, limits: &Limits) -> Vec<String> {
+        violations(problem, limits).into_iter().map(|violation| violation.field).collect()
+    }
+
+    #[test]
+    fn reports_all_violations_with_their_pointers() {
+        let valid = problem("10", &["4", "5"], &["3", "4"]);
+        assert!(pointers(&valid, &Limits::default()).is_empty());
+
+        let invalid = Problem {
+            epsilon: Some(1.5),
+            category_limits: Some(BTreeMap::from([("a/b".to_string(), 3_000_000_000)])),
+            owner: Some(String::new()),
+            tags: Some(vec!["ok".to_string(), String::new()]),
+            ..problem("10", &["4", "5"], &["3"])
+        };
+        assert_eq!(pointers(&invalid, &Limits::default()),
+                   ["/values", "/categories", "/category_limits/a~1b", "/category_limits", "/epsilon",
+                    "/owner", "/tags/1"]);
+    }
+
+    #[test]
+    fn checks_scaled_numbers_once_the_rest_is_valid() {
+        let too_large = ["4", "9223372036854775808"];
+        assert_eq!(pointers(&problem("10", &too_large, &["3", "4"]), &Limits::default()), ["/weights/1"]);
+        assert_eq!(pointers(&problem("10", &too_large, &["3"]), &Limits::default()), ["/values"]);
+    }
+
+    #[test]
+    fn honours_the_limits() {
+        let limits = Limits { max_items: 2, max_capacity: 10, callbacks: false };
+        assert!(pointers(&problem("10.0", &["4", "5"], &["3", "4"]), &limits).is_empty());
+
+        let beyond = Problem {
+            callback_url: Some("https://example.com/knapsacks".to_string()),
+            ..problem("10.5", &["4", "5", "6"], &["3", "4", "5"])
+        };
+        assert_eq!(pointers(&beyond, &limits), ["/weights", "/capacity", "/callback_url"]);
+        assert!(pointers(&beyond, &Limits::default()).is_empty());
+    }
+}
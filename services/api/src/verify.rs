@@ -0,0 +1,179 @@
+//! Checks packed items against a problem, recomputing their total weight and
+//! value. This is used both for client-supplied packings and for solutions
+//! read back from the db.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::decimal::Decimal;
+use crate::dto::{Problem, ScaledProblem};
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct VerifyBody {
+    pub problem: Problem,
+    pub packed_items: Vec<u32>,
+    // checked against the recomputed total value, if given
+    #[serde(default)]
+    pub total_value: Option<Decimal>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Verification {
+    pub valid: bool,
+    pub total_weight: Decimal,
+    pub total_value: Decimal,
+    // only set for robust problems
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worst_case_weight: Option<Decimal>,
+    // human-readable reasons why the packing isn't valid
+    pub violations: Vec<String>,
+}
+
+/// The checks are done on the scaled integers, where summing at most 2^32
+/// items of 64 bits each can't overflow 128 bits
+pub fn verify(problem: &ScaledProblem, packed_items: &[u32], total_value: Option<Decimal>) -> Verification {
+    let ScaledProblem { weights, values, capacity, precision, .. } = problem;
+    let mut violations = vec![];
+    let mut seen = HashSet::new();
+    let mut distinct = vec![];
+    let mut recomputed_weight: u128 = 0;
+    let mut recomputed_value: u128 = 0;
+
+    for item in packed_items {
+        let index = *item as usize;
+        if index >= weights.len() || index >= values.len() {
+            violations.push(format!("item {} is out of range", item));
+        } else if !seen.insert(index) {
+            violations.push(format!("item {} is packed more than once", item));
+        } else {
+            distinct.push(*item);
+            recomputed_weight += u128::from(weights[index]);
+            recomputed_value += u128::from(values[index]);
+        }
+    }
+
+    let total_weight = Decimal::from_scaled(recomputed_weight, precision.weights);
+    let recomputed = Decimal::from_scaled(recomputed_value, precision.values);
+
+    if recomputed_weight > u128::from(*capacity) {
+        violations.push(format!("total weight {} exceeds capacity {}", total_weight,
+                                Decimal::from_scaled(u128::from(*capacity), precision.weights)));
+    }
+
+    violations.extend(problem.limit_violations(&distinct));
+    violations.extend(problem.constraint_violations(&distinct));
+
+    let worst_case_weight = problem.gamma.map(|_| problem.worst_case_weight(&distinct));
+    if let Some(worst_case_weight) = worst_case_weight {
+        if recomputed_weight <= u128::from(*capacity) && worst_case_weight > u128::from(*capacity) {
+            violations.push(format!("worst case weight {} exceeds capacity {}",
+                                    Decimal::from_scaled(worst_case_weight, precision.weights),
+                                    Decimal::from_scaled(u128::from(*capacity), precision.weights)));
+        }
+    }
+
+    // The total value may be given with more decimals than the values, as
+    // long as they're zeros
+    if let Some(total_value) = total_value {
+        let scale = total_value.scale.max(precision.values);
+        let matches = total_value.rescale(scale)
+            .zip(recomputed.rescale(scale))
+            .is_some_and(|(given, recomputed)| given == recomputed);
+        if !matches {
+            violations.push(format!("total value {} does not match packed items ({})",
+                                    total_value, recomputed));
+        }
+    }
+
+    Verification {
+        valid: violations.is_empty(),
+        total_weight,
+        total_value: recomputed,
+        worst_case_weight: worst_case_weight
+            .map(|w| Decimal::from_scaled(w, precision.weights)),
+        violations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::dto::Constraint;
+
+    fn problem(capacity: &str, weights: &[&str], values: &[&str]) -> Problem {
+        let decimals = |numbers: &[&str]| numbers.iter().map(|n| Decimal::parse(n).unwrap()).collect();
+        Problem::new(Decimal::parse(capacity).unwrap(), decimals(weights), decimals(values))
+    }
+
+    fn violations(problem: &Problem, packed_items: &[u32], total_value: Option<&str>) -> Vec<String> {
+        let total_value = total_value.map(|v| Decimal::parse(v).unwrap());
+        verify(&problem.scaled().unwrap(), packed_items, total_value).violations
+    }
+
+    #[test]
+    fn recomputes_the_totals_in_the_units_given() {
+        let problem = problem("10", &["4.5", "5"], &["3.25", "4"]);
+        let verification = verify(&problem.scaled().unwrap(), &[0, 1], None);
+        assert!(verification.valid);
+        assert_eq!(verification.total_weight.to_string(), "9.5");
+        assert_eq!(verification.total_value.to_string(), "7.25");
+        assert!(violations(&problem, &[0, 1], Some("7.250")).is_empty());
+    }
+
+    #[test]
+    fn rejects_overweight_and_malformed_packings() {
+        let problem = problem("10", &["4", "5", "6"], &["3", "4", "5"]);
+        assert_eq!(violations(&problem, &[1, 2], None), ["total weight 11 exceeds capacity 10"]);
+        assert_eq!(violations(&problem, &[0, 3, 0], None),
+                   ["item 3 is out of range", "item 0 is packed more than once"]);
+    }
+
+    #[test]
+    fn rejects_a_wrong_total_value() {
+        let problem = problem("10", &["4", "5"], &["3", "4"]);
+        assert_eq!(violations(&problem, &[0, 1], Some("8")), ["total value 8 does not match packed items (7)"]);
+        // More decimals than the values have can't match either
+        assert_eq!(violations(&problem, &[0, 1], Some("7.001")).len(), 1);
+    }
+
+    #[test]
+    fn rejects_packings_which_dont_fit_in_the_worst_case() {
+        let problem = Problem {
+            deviations: Some(["1", "3", "2"].iter().map(|d| Decimal::parse(d).unwrap()).collect()),
+            gamma: Some(1),
+            ..problem("12", &["4", "5", "3"], &["3", "4", "5"])
+        };
+        let verification = verify(&problem.scaled().unwrap(), &[0, 1, 2], None);
+        assert_eq!(verification.violations, ["worst case weight 15 exceeds capacity 12"]);
+        assert_eq!(verification.worst_case_weight.map(|w| w.to_string()), Some("15".to_string()));
+        assert!(violations(&problem, &[0, 2], None).is_empty());
+    }
+
+    #[test]
+    fn rejects_packings_exceeding_the_item_limits() {
+        let problem = Problem {
+            max_items: Some(2),
+            categories: Some(vec![Some("a".to_string()), Some("a".to_string()), None]),
+            category_limits: Some(BTreeMap::from([("a".to_string(), 1)])),
+            ..problem("100", &["1", "1", "1"], &["1", "1", "1"])
+        };
+        assert_eq!(violations(&problem, &[0, 1, 2], None),
+                   ["3 items exceed the limit of 2", "2 items of category a exceed its limit of 1"]);
+        assert!(violations(&problem, &[1, 2], None).is_empty());
+    }
+
+    #[test]
+    fn rejects_packings_violating_a_constraint() {
+        let problem = Problem {
+            constraints: Some(vec![Constraint { coefficients: vec![1.0, -1.0, 0.0], bound: 0.0 },
+                                   Constraint { coefficients: vec![0.0, 0.0, 1.0], bound: 0.5 }]),
+            ..problem("100", &["1", "1", "1"], &["1", "1", "1"])
+        };
+        assert_eq!(violations(&problem, &[0, 2], None),
+                   ["constraint 0 exceeds its bound of 0", "constraint 1 exceeds its bound of 0.5"]);
+        assert!(violations(&problem, &[0, 1], None).is_empty());
+    }
+}
@@ -0,0 +1,445 @@
+//! Posts the knapsack of a task to the `callback_url` of its problem once the
+//! task has completed, failed or been cancelled, so that clients needn't poll
+//! `GET /knapsack/<id>`.
+//!
+//! Whoever finishes the task, the optimizer or `DELETE /knapsack/<id>`,
+//! enqueues its delivery in the same transaction, and `run` attempts the
+//! deliveries which are due. The knapsack is posted as `GET /knapsack/<id>`
+//! returns it at the time, signed with the secret `WEBHOOK_SECRET`, see
+//! `sign`. An attempt fails unless the response is 2xx within `TIMEOUT`, and
+//! is then retried with exponential backoff, up to `MAX_ATTEMPTS` times. All
+//! attempts are logged, see `GET /knapsack/<id>/deliveries`.
+//!
+//! Callbacks only go to public hosts, so that they can't be used to reach the
+//! api's own host or network. The host of the url is checked when the problem
+//! is validated, and the addresses it resolves to whenever it's delivered to.
+//! Redirects aren't followed.
+
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use utoipa::ToSchema;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::db;
+use crate::db::models::{Delivery, DeliveryAttempt};
+use crate::{ApiError, ApiResult};
+
+pub const MAX_URL_LENGTH: usize = 2048;
+
+pub const SIGNATURE_HEADER: &str = "Knapsack-Signature";
+
+/// How often the due deliveries are looked for, and how many of them are
+/// attempted at once
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BATCH_SIZE: i64 = 16;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Seconds a claimed delivery is leased for, which is longer than an attempt
+/// takes
+const LEASE: i32 = 60;
+
+/// Number of attempts before a delivery fails, where the first retry is after
+/// `FIRST_RETRY` seconds, and each later one after twice as long as the last
+const MAX_ATTEMPTS: i32 = 8;
+const FIRST_RETRY: i32 = 10;
+
+/// Whether the url is an http(s) url of a host which isn't internal, as far
+/// as can be told without resolving it
+pub fn is_valid_url(url: &str) -> bool {
+    use url::Host;
+    url.len() <= MAX_URL_LENGTH
+        && Url::parse(url).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https") && match url.host() {
+                Some(Host::Domain(domain)) => domain != "localhost" && !domain.ends_with(".localhost"),
+                Some(Host::Ipv4(ip)) => !is_internal(ip.into()),
+                Some(Host::Ipv6(ip)) => !is_internal(ip.into()),
+                None => false,
+            }
+        })
+}
+
+/// Whether the address is one of the host itself, or of a private network
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            // "this network" 0.0.0.0/8 and carrier-grade NAT 100.64.0.0/10
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_broadcast() || ip.is_multicast()
+                || first == 0 || (first == 100 && second & 0xc0 == 64)
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(ip.into()),
+            None => {
+                let segments = ip.segments();
+                // NAT64 64:ff9b::/96 reaches the IPv4 address in its last 32 bits
+                if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                    let ipv4 = (u32::from(segments[6]) << 16) | u32::from(segments[7]);
+                    return is_internal(Ipv4Addr::from(ipv4).into());
+                }
+                // unique local fc00::/7, link-local fe80::/10 and site-local fec0::/10
+                let first = segments[0];
+                ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80 || first & 0xffc0 == 0xfec0
+            },
+        },
+    }
+}
+
+/// Resolves hosts as the system does, but fails for those which resolve to an
+/// internal address, which a public name may do by the time it's delivered to
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            // The port is set by the client
+            let addrs: Vec<SocketAddr> = rocket::tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.iter().any(|addr| is_internal(addr.ip())) {
+                return Err(format!("{} resolves to an internal address", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// The client which delivers to public hosts only, without following
+/// redirects, which could lead anywhere
+fn client() -> Client {
+    Client::builder()
+        .timeout(TIMEOUT)
+        .user_agent("knapsack-service")
+        .redirect(Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Could not create http client")
+}
+
+/// Attempts the due deliveries, forever
+pub async fn run(conn: db::Connection, secret: String) {
+    let client = client();
+
+    loop {
+        let claimed = match db::claim_deliveries(BATCH_SIZE, LEASE, &conn) {
+            Ok(deliveries) => {
+                let claimed = deliveries.len();
+                futures::future::join_all(deliveries.into_iter()
+                    .map(|delivery| attempt(delivery, &client, secret.as_bytes(), &conn)))
+                    .await;
+                claimed
+            },
+            Err(e) => {
+                eprintln!("Could not claim deliveries: {}", e);
+                0
+            },
+        };
+        // More deliveries may be due right away
+        if claimed < BATCH_SIZE as usize {
+            rocket::tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Attempts the delivery, and logs the attempt
+async fn attempt(mut delivery: Delivery, client: &Client, secret: &[u8], conn: &db::Connection) {
+    let response = match db::get(delivery.task_id, conn) {
+        // Urls of hosts given by their address aren't resolved, and urls
+        // stored before they were checked may be internal
+        Ok(_) if !is_valid_url(&delivery.callback_url) => Err("Not a public url".to_string()),
+        Ok(knapsack) => match serde_json::to_vec(&knapsack) {
+            Ok(body) => send(client, &delivery.callback_url, secret, body).await,
+            Err(e) => Err(e.to_string()),
+        },
+        Err(e) => Err(format!("Could not read task: {}", e)),
+    };
+
+    let now = crate::current_time();
+    delivery.attempts += 1;
+    if response.as_ref().is_ok_and(|status| (200..300).contains(status)) {
+        delivery.status = DeliveryStatus::Delivered.to_string();
+        delivery.ts_delivered = Some(now);
+    } else if delivery.attempts >= MAX_ATTEMPTS {
+        delivery.status = DeliveryStatus::Failed.to_string();
+    } else {
+        delivery.ts_next_attempt = now + retry_delay(delivery.attempts);
+    }
+
+    let attempt = DeliveryAttempt {
+        task_id: delivery.task_id,
+        attempt: delivery.attempts,
+        ts_attempted: now,
+        response_status: response.as_ref().ok().map(|status| i32::from(*status)),
+        error: response.err(),
+    };
+    if let Err(e) = db::record_delivery_attempt(&delivery, &attempt, conn) {
+        // The lease expires, after which the delivery is attempted again
+        eprintln!("Could not log delivery of task {}: {}", delivery.task_id, e);
+    }
+}
+
+/// Seconds from the failed attempt until the next one
+fn retry_delay(attempts: i32) -> i32 {
+    FIRST_RETRY << (attempts - 1).clamp(0, 16)
+}
+
+/// Posts the signed body to the url, returning the status of the response, or
+/// why there was none
+pub async fn send(client: &Client, url: &str, secret: &[u8], body: Vec<u8>) -> Result<u16, String> {
+    let signature = sign(secret, crate::current_time(), &body);
+    client.post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map(|response| response.status().as_u16())
+        .map_err(|e| describe(&e))
+}
+
+/// The error along with its causes, which say e.g. that the host is internal
+fn describe(error: &dyn std::error::Error) -> String {
+    match error.source() {
+        Some(source) => format!("{}: {}", error, describe(source)),
+        None => error.to_string(),
+    }
+}
+
+/// The signature `t=<time>,v1=<hmac>`, where `hmac` is the HMAC-SHA256 of
+/// `<time>.<body>` in hex. Receivers should recompute it, and reject
+/// deliveries which are too old, to prevent replays.
+pub fn sign(secret: &[u8], time: i32, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(time.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let hmac: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("t={},v1={}", time, hmac)
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn from_str(status: &str) -> ApiResult<Self> {
+        use DeliveryStatus::*;
+        match status {
+            "pending" => Ok(Pending),
+            "delivered" => Ok(Delivered),
+            "failed" => Ok(Failed),
+            _ => Err(ApiError::internal(format!("Unknown delivery status {}", status)))
+        }
+    }
+}
+
+impl Display for DeliveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryStatus::Pending => write!(f, "pending"),
+            DeliveryStatus::Delivered => write!(f, "delivered"),
+            DeliveryStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeliveryLog {
+    pub task: Uuid,
+    pub callback_url: String,
+    pub status: DeliveryStatus,
+    // only set while the delivery is pending
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered: Option<i32>,
+    pub attempts: Vec<Attempt>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Attempt {
+    pub attempted: i32,
+    // the status of the response, or why there was none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DeliveryLog {
+    pub fn from_db(delivery: Delivery, attempts: Vec<DeliveryAttempt>) -> ApiResult<Self> {
+        let status = DeliveryStatus::from_str(&delivery.status)?;
+        Ok(Self {
+            task: delivery.task_id,
+            callback_url: delivery.callback_url,
+            next_attempt: matches!(status, DeliveryStatus::Pending).then_some(delivery.ts_next_attempt),
+            delivered: delivery.ts_delivered,
+            status,
+            attempts: attempts.into_iter()
+                .map(|attempt| Attempt {
+                    attempted: attempt.ts_attempted,
+                    response_status: attempt.response_status,
+                    error: attempt.error,
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    /// The headers and body of a request
+    type Request = (Vec<String>, Vec<u8>);
+
+    /// A receiver of one request at the returned url, which it responds to
+    /// with the status, and then returns along with its headers
+    fn stand_in(status: u16) -> (String, JoinHandle<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/callback", listener.local_addr().unwrap());
+        let receiver = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                match line.trim_end() {
+                    "" => break,
+                    header => headers.push(header.to_lowercase()),
+                }
+            }
+            let length = headers.iter()
+                .find_map(|header| header.strip_prefix("content-length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(stream, "HTTP/1.1 {} Stand-in\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status).unwrap();
+            (headers, body)
+        });
+        (url, receiver)
+    }
+
+    #[rocket::async_test]
+    async fn posts_signed_body() {
+        let (url, receiver) = stand_in(204);
+        let body = br#"{"task": "2881a781-9c3d-4eba-b450-290d98c68026"}"#.to_vec();
+        let status = send(&Client::new(), &url, b"secret", body.clone()).await;
+        let (headers, received) = receiver.join().unwrap();
+
+        assert_eq!(status, Ok(204));
+        assert_eq!(received, body);
+        assert!(headers.contains(&"content-type: application/json".to_string()));
+        let signature = headers.iter()
+            .find_map(|header| header.strip_prefix("knapsack-signature: "))
+            .unwrap();
+        let time = signature.strip_prefix("t=").unwrap().split(',').next().unwrap();
+        assert_eq!(signature, sign(b"secret", time.parse().unwrap(), &body));
+        assert_ne!(signature, sign(b"other secret", time.parse().unwrap(), &body));
+    }
+
+    #[rocket::async_test]
+    async fn reports_status_of_rejected_delivery() {
+        let (url, receiver) = stand_in(503);
+        let status = send(&Client::new(), &url, b"secret", b"{}".to_vec()).await;
+        receiver.join().unwrap();
+        assert_eq!(status, Ok(503));
+    }
+
+    #[rocket::async_test]
+    async fn reports_unreachable_receiver() {
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/callback", listener.local_addr().unwrap())
+        };
+        assert!(send(&Client::new(), &url, b"secret", b"{}".to_vec()).await.is_err());
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let delays: Vec<i32> = (1..MAX_ATTEMPTS).map(retry_delay).collect();
+        assert_eq!(delays, [10, 20, 40, 80, 160, 320, 640]);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_hosts_which_resolve_to_internal_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://localhost:{}/callback", listener.local_addr().unwrap().port());
+        let error = send(&client(), &url, b"secret", b"{}".to_vec()).await.unwrap_err();
+        assert!(error.contains("internal address"), "{}", error);
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn accepts_only_public_http_urls() {
+        assert!(is_valid_url("https://example.com/callback?task=1"));
+        assert!(is_valid_url("http://93.184.215.14:8080"));
+        assert!(is_valid_url("http://[2606:2800:21f:cb07:6820:80da:af6b:8b2c]"));
+        for internal in ["http://localhost:8080", "http://api.localhost", "http://127.0.0.1",
+                         "http://10.0.0.1", "http://172.16.0.1", "http://192.168.1.1",
+                         "http://169.254.169.254/latest/meta-data", "http://0.0.0.0", "http://[::1]",
+                         "http://[fd00::1]", "http://[fe80::1]", "http://[::ffff:127.0.0.1]"] {
+            assert!(!is_valid_url(internal), "{}", internal);
+        }
+        assert!(!is_valid_url("ftp://example.com"));
+        assert!(!is_valid_url("example.com/callback"));
+        assert!(!is_valid_url(&format!("https://example.com/{}", "a".repeat(MAX_URL_LENGTH))));
+    }
+
+    #[test]
+    fn treats_carrier_grade_nat_as_internal() {
+        assert!(is_internal("100.64.0.1".parse().unwrap()));
+        assert!(is_internal("100.127.255.254".parse().unwrap()));
+        assert!(!is_internal("100.63.255.255".parse().unwrap()));
+        assert!(!is_internal("100.128.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn treats_this_network_and_broadcast_as_internal() {
+        assert!(is_internal("0.1.2.3".parse().unwrap()));
+        assert!(is_internal("255.255.255.255".parse().unwrap()));
+        assert!(!is_internal("1.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn treats_multicast_as_internal() {
+        assert!(is_internal("224.0.0.1".parse().unwrap()));
+        assert!(is_internal("239.255.255.250".parse().unwrap()));
+        assert!(is_internal("ff02::1".parse().unwrap()));
+        assert!(is_internal("ff0e::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn treats_site_local_as_internal() {
+        assert!(is_internal("fec0::1".parse().unwrap()));
+        assert!(is_internal("feff::1".parse().unwrap()));
+        assert!(!is_internal("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn treats_nat64_of_internal_addresses_as_internal() {
+        assert!(is_internal("64:ff9b::a00:1".parse().unwrap()));
+        assert!(is_internal("64:ff9b::7f00:1".parse().unwrap()));
+        assert!(is_internal("64:ff9b::a9fe:a9fe".parse().unwrap()));
+        assert!(!is_internal("64:ff9b::5db8:d70e".parse().unwrap()));
+    }
+}
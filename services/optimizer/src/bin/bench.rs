@@ -0,0 +1,332 @@
+//! Benchmarks the solvers on a fixed corpus of instances with known optima,
+//! `bench/corpus.json`, recording the gap to the optimum, the time and the
+//! peak memory of each run. The report is written as JSON, by default to
+//! `bench/report.json`, and compared to a stored baseline,
+//! `bench/baseline.json`: the benchmark fails if the mean gap of a solver grew
+//! by more than a threshold, or if a solver failed on an instance it solved in
+//! the baseline. Times and memory are only reported, as they depend on the
+//! machine.
+//!
+//! ```text
+//! bench [--corpus <file>] [--baseline <file>] [--output <file>]
+//!       [--threshold <gap>] [--algorithms <algorithm,...>]
+//! ```
+//!
+//! To update the baseline after an intended change, write the report to it
+//! with `--output bench/baseline.json`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use optimizer::db::models::{map_arr, Task};
+use optimizer::{solver, verify};
+
+const USAGE: &str = "\
+Usage: bench [--corpus <file>] [--baseline <file>] [--output <file>]
+             [--threshold <gap>] [--algorithms <algorithm,...>]";
+
+const FLAGS: [&str; 5] = ["corpus", "baseline", "output", "threshold", "algorithms"];
+
+/// The solvers benchmarked by default, where the `fptas` runs with
+/// `FPTAS_EPSILON`
+#[cfg(not(feature = "milp"))]
+const ALGORITHMS: &str = "genetic,fptas,branch_and_bound";
+#[cfg(feature = "milp")]
+const ALGORITHMS: &str = "genetic,fptas,branch_and_bound,milp";
+const FPTAS_EPSILON: f64 = 0.1;
+
+/// Largest increase of the mean gap of a solver which isn't a regression
+const THRESHOLD: f64 = 0.01;
+
+/// Counts the bytes allocated by all threads, and the peak since it was last
+/// reset
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// An instance of the corpus
+#[derive(Deserialize)]
+struct Instance {
+    name: String,
+    problem: Problem,
+    optimum: i64,
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    capacity: i64,
+    weights: Vec<u64>,
+    values: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Report {
+    runs: Vec<Run>,
+    summaries: Vec<Summary>,
+}
+
+/// A run of a solver on an instance
+#[derive(Serialize, Deserialize)]
+struct Run {
+    instance: String,
+    algorithm: String,
+    optimum: i64,
+    // only set if the solver returned a valid packing
+    value: Option<i64>,
+    // relative to the optimum
+    gap: Option<f64>,
+    seconds: f64,
+    peak_bytes: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// All runs of a solver, where the gaps are of the successful runs
+#[derive(Serialize, Deserialize)]
+struct Summary {
+    algorithm: String,
+    runs: usize,
+    failures: usize,
+    mean_gap: f64,
+    max_gap: f64,
+    seconds: f64,
+    peak_bytes: usize,
+}
+
+fn main() {
+    match run() {
+        Ok(regressions) if regressions.is_empty() => {},
+        Ok(regressions) => {
+            for regression in regressions {
+                eprintln!("Regression: {}", regression);
+            }
+            std::process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        },
+    }
+}
+
+/// Runs the benchmark, returning the regressions compared to the baseline
+fn run() -> Result<Vec<String>, String> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let arg = |name: &str, default: &'static str| {
+        args.get(name).map(String::as_str).unwrap_or(default)
+    };
+    let threshold: f64 = match args.get("threshold") {
+        Some(threshold) => threshold.parse().map_err(|e| format!("--threshold: {}", e))?,
+        None => THRESHOLD,
+    };
+
+    let corpus: Vec<Instance> = read_json(arg("corpus", "bench/corpus.json"))?;
+    let mut runs = vec![];
+    for algorithm in arg("algorithms", ALGORITHMS).split(',') {
+        for instance in &corpus {
+            runs.push(bench(instance, algorithm));
+        }
+    }
+    let report = Report { summaries: summarise(&runs), runs };
+
+    // Not written to stdout, where the genetic solver reports its progress
+    let output = arg("output", "bench/report.json");
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    std::fs::write(output, json + "\n").map_err(|e| format!("Can't write {}: {}", output, e))?;
+    for summary in &report.summaries {
+        eprintln!("{:<20} mean gap {:.4}, max gap {:.4}, {} failures, {:.3} s, peak {} bytes",
+                  summary.algorithm, summary.mean_gap, summary.max_gap, summary.failures,
+                  summary.seconds, summary.peak_bytes);
+    }
+
+    let path = arg("baseline", "bench/baseline.json");
+    if output == path {
+        return Ok(vec![]);
+    }
+    let baseline: Report = read_json(path)?;
+    Ok(regressions(&report, &baseline, threshold))
+}
+
+/// Solves the instance with the algorithm, measuring the time and peak memory
+fn bench(instance: &Instance, algorithm: &str) -> Run {
+    let task = task(instance, algorithm);
+
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(allocated, Ordering::Relaxed);
+    let start = Instant::now();
+    let solutions = solver::solve(&task, &solver::Cancellation::default(),
+                                  &solver::Reporter::default());
+    let seconds = start.elapsed().as_secs_f64();
+    let peak_bytes = PEAK.load(Ordering::Relaxed).saturating_sub(allocated);
+
+    // Only valid packings count, which can't beat the optimum
+    let value = solutions.and_then(|solutions| {
+        let solution = solutions.first().ok_or("No solution")?;
+        verify::verify(&task, solution)?;
+        if solution.total_value > instance.optimum {
+            return Err(format!("Value {} exceeds the known optimum", solution.total_value));
+        }
+        Ok(solution.total_value)
+    });
+
+    let gap = |value: i64| match instance.optimum {
+        0 => 0.0,
+        optimum => (optimum - value) as f64 / optimum as f64,
+    };
+    Run {
+        instance: instance.name.clone(),
+        algorithm: algorithm.to_string(),
+        optimum: instance.optimum,
+        value: value.as_ref().ok().copied(),
+        gap: value.as_ref().ok().map(|value| gap(*value)),
+        seconds,
+        peak_bytes,
+        error: value.err(),
+    }
+}
+
+/// A task as the optimizer would read it from the db
+fn task(instance: &Instance, algorithm: &str) -> Task {
+    let to_i64 = |numbers: &[u64]| map_arr(&numbers.iter().map(|n| *n as i64).collect::<Vec<_>>());
+    Task {
+        id: Uuid::new_v4(),
+        status: "started".to_string(),
+        ts_submitted: 0,
+        ts_started: None,
+        ts_completed: None,
+        problem_capacity: instance.problem.capacity,
+        problem_weights: to_i64(&instance.problem.weights),
+        problem_values: to_i64(&instance.problem.values),
+        algorithm: algorithm.to_string(),
+        epsilon: (algorithm == "fptas").then_some(FPTAS_EPSILON),
+        initial_solution: None,
+        sensitivity: false,
+        alternatives: None,
+        optimal_only: false,
+        tie_break: None,
+        parent_id: None,
+        removed_items: None,
+        inherited_bound: None,
+        problem_deviations: None,
+        gamma: None,
+        max_items: None,
+        categories: None,
+        category_names: None,
+        category_limits: None,
+        constraint_coefficients: None,
+        constraint_bounds: None,
+        keep_best: None,
+        owner: None,
+        tags: None,
+        idempotency_key: None,
+        request_hash: None,
+        callback_url: None,
+    }
+}
+
+/// Summarises the runs per algorithm, in the order they were run
+fn summarise(runs: &[Run]) -> Vec<Summary> {
+    let mut summaries: Vec<Summary> = vec![];
+    for run in runs {
+        let index = match summaries.iter().position(|s| s.algorithm == run.algorithm) {
+            Some(index) => index,
+            None => {
+                summaries.push(Summary {
+                    algorithm: run.algorithm.clone(),
+                    runs: 0,
+                    failures: 0,
+                    mean_gap: 0.0,
+                    max_gap: 0.0,
+                    seconds: 0.0,
+                    peak_bytes: 0,
+                });
+                summaries.len() - 1
+            },
+        };
+        let summary = &mut summaries[index];
+        summary.runs += 1;
+        summary.seconds += run.seconds;
+        summary.peak_bytes = summary.peak_bytes.max(run.peak_bytes);
+        match run.gap {
+            Some(gap) => {
+                summary.mean_gap += gap;
+                summary.max_gap = summary.max_gap.max(gap);
+            },
+            None => summary.failures += 1,
+        }
+    }
+    for summary in &mut summaries {
+        let solved = summary.runs - summary.failures;
+        if solved > 0 {
+            summary.mean_gap /= solved as f64;
+        }
+    }
+    summaries
+}
+
+fn regressions(report: &Report, baseline: &Report, threshold: f64) -> Vec<String> {
+    let mut regressions = vec![];
+    for summary in &report.summaries {
+        let Some(before) = baseline.summaries.iter().find(|s| s.algorithm == summary.algorithm) else {
+            continue;
+        };
+        if summary.mean_gap > before.mean_gap + threshold {
+            regressions.push(format!("{}: mean gap {:.4} exceeds the baseline {:.4} by more than {}",
+                                     summary.algorithm, summary.mean_gap, before.mean_gap, threshold));
+        }
+    }
+    for run in report.runs.iter().filter(|run| run.error.is_some()) {
+        let solved_before = baseline.runs.iter().any(|before| {
+            before.instance == run.instance && before.algorithm == run.algorithm
+                && before.error.is_none()
+        });
+        if solved_before {
+            regressions.push(format!("{} on {}: {}", run.algorithm, run.instance,
+                                     run.error.as_deref().unwrap_or_default()));
+        }
+    }
+    regressions
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &str) -> Result<T, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    serde_json::from_str(&json).map_err(|e| format!("Can't parse {}: {}", path, e))
+}
+
+/// Parses `--name value` pairs
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<HashMap<String, String>, String> {
+    let mut parsed = HashMap::new();
+    while let Some(flag) = args.next() {
+        let name = flag.strip_prefix("--")
+            .filter(|name| FLAGS.contains(name))
+            .ok_or(format!("Unexpected argument {}", flag))?;
+        let value = args.next().ok_or(format!("{} needs a value", flag))?;
+        parsed.insert(name.to_string(), value);
+    }
+    Ok(parsed)
+}
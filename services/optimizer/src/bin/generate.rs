@@ -0,0 +1,101 @@
+//! Generates a series of benchmark instances, see `generator.rs`, and either
+//! writes them as a JSON array of request bodies for `POST /knapsack`, or
+//! submits them to a running API.
+//!
+//! ```text
+//! generate --class <class> --n <items> --range <R> [--seed <seed>]
+//!          [--count <instances>] [--algorithm <algorithm>]
+//!          [--output <file> | --submit <api url>]
+//! ```
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use optimizer::generator::{Class, Spec};
+
+const USAGE: &str = "\
+Usage: generate --class <class> --n <items> --range <R> [--seed <seed>]
+                [--count <instances>] [--algorithm <algorithm>]
+                [--output <file> | --submit <api url>]
+
+Classes: uncorrelated, weakly_correlated, strongly_correlated,
+inverse_strongly_correlated, almost_strongly_correlated, subset_sum,
+uncorrelated_spanner, weakly_correlated_spanner, strongly_correlated_spanner
+
+Writes the instances to stdout unless --output or --submit is given.";
+
+const FLAGS: [&str; 8] = ["class", "n", "range", "seed", "count", "algorithm", "output", "submit"];
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let arg = |name: &str| args.get(name).map(String::as_str);
+    let number = |name: &str, default: Option<u64>| -> Result<u64, String> {
+        match (arg(name), default) {
+            (Some(value), _) => value.parse()
+                .map_err(|e| format!("--{} {}: {}", name, value, e)),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(format!("--{} is required", name)),
+        }
+    };
+
+    let class: Class = arg("class").ok_or("--class is required")?.parse()?;
+    let n = usize::try_from(number("n", None)?).map_err(|e| e.to_string())?;
+    let spec = Spec::new(class, n, number("range", None)?, number("seed", Some(0))?)?;
+    let count = u32::try_from(number("count", Some(1))?).map_err(|e| e.to_string())?;
+
+    let bodies: Vec<Value> = spec.series(count).into_iter()
+        .map(|instance| {
+            let mut problem = json!(instance);
+            if let Some(algorithm) = arg("algorithm") {
+                problem["algorithm"] = json!(algorithm);
+            }
+            json!({"problem": problem})
+        })
+        .collect();
+
+    match (arg("output"), arg("submit")) {
+        (Some(_), Some(_)) => Err("--output and --submit can't both be given".to_string()),
+        (Some(path), None) => std::fs::write(path, json!(bodies).to_string())
+            .map_err(|e| format!("Can't write {}: {}", path, e)),
+        (None, Some(url)) => submit(url, &bodies),
+        (None, None) => {
+            println!("{}", json!(bodies));
+            Ok(())
+        },
+    }
+}
+
+/// Submits the request bodies in order, printing the id of each task
+fn submit(url: &str, bodies: &[Value]) -> Result<(), String> {
+    let url = format!("{}/knapsack", url.trim_end_matches('/'));
+    for body in bodies {
+        let response: Value = ureq::post(&url)
+            .send_json(body)
+            .map_err(|e| format!("Can't submit to {}: {}", url, e))?
+            .into_json()
+            .map_err(|e| e.to_string())?;
+        println!("{}", response["task"].as_str().ok_or("Response without a task id")?);
+    }
+    Ok(())
+}
+
+/// Parses `--name value` pairs
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<HashMap<String, String>, String> {
+    let mut parsed = HashMap::new();
+    while let Some(flag) = args.next() {
+        let name = flag.strip_prefix("--")
+            .filter(|name| FLAGS.contains(name))
+            .ok_or(format!("Unexpected argument {}", flag))?;
+        let value = args.next().ok_or(format!("{} needs a value", flag))?;
+        parsed.insert(name.to_string(), value);
+    }
+    Ok(parsed)
+}
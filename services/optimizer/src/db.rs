@@ -18,10 +18,17 @@ pub mod models;
 use models::Task;
 use crate::get_var;
 
-use self::models::Solution;
+use self::models::{SensitivityReport, Solution};
+use crate::solver::Progress;
 
 pub type Connection = Arc<Mutex<PgConnection>>;
 
+/// Channels on which changes of the status of a task are notified, with its
+/// id as payload, and the progress of its solver, as JSON, which the api
+/// waits for
+const TASK_UPDATES: &str = "task_updates";
+const TASK_PROGRESS: &str = "task_progress";
+
 pub async fn init() -> Connection {
     let user = get_var("DATABASE_USER");
     let pass = get_var("DATABASE_PASS");
@@ -39,63 +46,208 @@ pub async fn init() -> Connection {
     Arc::new(Mutex::new(connection))
 }
 
-pub fn set_task_status_started(task_id: Uuid, conn: &Connection) -> Result<Task, String> {
+/// Starts the task, unless it's no longer submitted, e.g. because it was
+/// cancelled, in which case there's nothing to do
+pub fn set_task_status_started(task_id: Uuid, conn: &Connection) -> Result<Option<Task>, String> {
 
     use schema::tasks::dsl::*;
 
     let mut conn = grab_lock(conn)?;
 
-    match diesel::update(schema::tasks::table)
-        .filter(id.eq(task_id))
-        .set((status.eq("started"),
-              ts_started.eq(current_time()),
-        ))
-        .returning(Task::as_returning())
-        .get_result(conn.deref_mut())
-    {
+    match conn.transaction(|conn| {
+        let task = diesel::update(schema::tasks::table)
+            .filter(id.eq(task_id))
+            .filter(status.eq("submitted"))
+            .set((status.eq("started"),
+                  ts_started.eq(current_time()),
+            ))
+            .returning(Task::as_returning())
+            .get_result(conn)
+            .optional()?;
+        if task.is_some() {
+            notify_update(task_id, conn)?;
+        }
+        Ok::<_, diesel::result::Error>(task)
+    }) {
         Ok(task) => Ok(task),
         Err(e) => Err(format!("{}",e)),
     }
 }
 
-pub fn insert_solution(solution: Solution, conn: &Connection) -> Result<(), String> {
+/// Whether the task was cancelled, and if so, whether the best solution found
+/// until then is kept
+pub fn get_cancellation(task_id: Uuid, conn: &Connection) -> Result<Option<bool>, String> {
     use schema::tasks::dsl::*;
-    let mut conn = grab_lock(&conn)?;
 
-    match diesel::insert_into(schema::solutions::table)
-        .values(&solution)
-        .execute(conn.deref_mut())
+    let mut conn = grab_lock(conn)?;
+
+    match tasks
+        .filter(id.eq(task_id))
+        .select((status, keep_best))
+        .get_result::<(String, Option<bool>)>(conn.deref_mut())
     {
+        Ok((task_status, keep)) => Ok((task_status == "cancelled").then_some(keep.unwrap_or(false))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Marks the task as failed, unless it was cancelled in the meantime
+pub fn set_task_status_failed(task_id: Uuid, conn: &Connection) -> Result<(), String> {
+    use schema::tasks::dsl::*;
+
+    let mut conn = grab_lock(conn)?;
+
+    match conn.transaction(|conn| {
+        let rows_updated = diesel::update(schema::tasks::table)
+            .filter(id.eq(task_id))
+            .filter(status.eq("started"))
+            .set((status.eq("failed"),
+                  ts_completed.eq(current_time()),
+            ))
+            .execute(conn)?;
+        if rows_updated == 1 {
+            insert_delivery(task_id, conn)?;
+            notify_update(task_id, conn)?;
+        }
+        Ok::<_, diesel::result::Error>(rows_updated)
+    }) {
         Ok(rows_updated) => {
             if rows_updated != 1 {
-                eprintln!("Warning: db: Expected 1, was {}", rows_updated)
+                println!("Task {} was cancelled before it failed", task_id)
             }
             Ok(())
         },
         Err(e) => Err(e.to_string()),
-    }?;
+    }
+}
 
-    match diesel::update(schema::tasks::table)
-        .filter(id.eq(solution.task_id))
-        .set((status.eq("completed"),
-            ts_completed.eq(current_time())))
-        .execute(conn.deref_mut())
-    {
-        Ok(rows_updated) => {
-            if rows_updated != 1 {
-                eprintln!("Warning: db: Expected 1, was {}", rows_updated)
+/// Inserts the ranked solutions of a task along with the sensitivity report of
+/// the best one, if any, and marks the task as completed unless it was
+/// cancelled, in which case the solutions are the best found before it was
+/// stopped, and only kept if the cancellation asked for it
+pub fn insert_solutions(solutions: &[Solution], report: Option<&SensitivityReport>, conn: &Connection)
+                        -> Result<(), String> {
+    use schema::tasks::dsl::*;
+    let task_id = solutions.first().ok_or("db: No solutions to insert")?.task_id;
+    let mut conn = grab_lock(conn)?;
+
+    // The solutions and report are inserted along with the status, so that
+    // they're never stored for a task which didn't keep them, and a retry
+    // doesn't insert them twice
+    match conn.transaction(|conn| {
+        let completed = diesel::update(schema::tasks::table)
+            .filter(id.eq(task_id))
+            .filter(status.eq("started"))
+            .set((status.eq("completed"),
+                ts_completed.eq(current_time())))
+            .execute(conn)? == 1;
+        let kept = completed || tasks
+            .filter(id.eq(task_id))
+            .filter(status.eq("cancelled"))
+            .select(keep_best)
+            .get_result::<Option<bool>>(conn)
+            .optional()?
+            .flatten()
+            .unwrap_or(false);
+        if !kept {
+            return Ok::<_, diesel::result::Error>((completed, None));
+        }
+        let rows_inserted = diesel::insert_into(schema::solutions::table)
+            .values(solutions)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if let Some(report) = report {
+            diesel::insert_into(schema::sensitivity_reports::table)
+                .values(report)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+        // A cancelled task is delivered once its best solutions are stored,
+        // as the api leaves that to the optimizer
+        insert_delivery(task_id, conn)?;
+        notify_update(task_id, conn)?;
+        Ok((completed, Some(rows_inserted)))
+    }) {
+        Ok((completed, rows_inserted)) => {
+            if !completed {
+                println!("Task {} was cancelled before it completed", task_id)
+            }
+            match rows_inserted {
+                None => println!("Discarding the solutions of task {}", task_id),
+                Some(rows) if rows != solutions.len() =>
+                    eprintln!("Warning: db: Expected {}, was {}", solutions.len(), rows),
+                Some(_) => {},
             }
             Ok(())
         },
         Err(e) => Err(e.to_string()),
     }
+}
+
+/// Notifies the change of the status of the task, once the transaction it's
+/// made in commits
+fn notify_update(task_id: Uuid, conn: &mut PgConnection) -> QueryResult<usize> {
+    notify(TASK_UPDATES, &task_id.to_string(), conn)
+}
+
+/// Notifies the progress of the solver of the task, which isn't stored, as
+/// it's only of interest while the task is running
+pub fn notify_progress(task_id: Uuid, progress: &Progress, conn: &Connection) -> Result<(), String> {
+    let payload = serde_json::json!({
+        "task": task_id,
+        "generation": progress.generation,
+        "best_value": progress.best_value,
+        "elapsed": progress.elapsed.as_secs_f64(),
+    });
+    let mut conn = grab_lock(conn)?;
+
+    match notify(TASK_PROGRESS, &payload.to_string(), conn.deref_mut()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn notify(channel: &str, payload: &str, conn: &mut PgConnection) -> QueryResult<usize> {
+    use diesel::sql_types::Text;
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(channel)
+        .bind::<Text, _>(payload)
+        .execute(conn)
+}
+
+/// Enqueues the delivery of a cancelled task which kept its best solution,
+/// though it didn't find any
+pub fn enqueue_delivery(task_id: Uuid, conn: &Connection) -> Result<(), String> {
+    let mut conn = grab_lock(conn)?;
+    insert_delivery(task_id, conn.deref_mut()).map(|_| ()).map_err(|e| e.to_string())
+}
 
-    // todo: retry
+/// Enqueues the delivery of the finished task to its callback url, if it has
+/// one and it isn't enqueued already. The api delivers it.
+fn insert_delivery(task_id: Uuid, conn: &mut PgConnection) -> QueryResult<usize> {
+    use diesel::sql_types::{Integer, Text};
+    use schema::{deliveries, tasks};
+    diesel::insert_into(deliveries::table)
+        .values(tasks::table
+            .filter(tasks::id.eq(task_id))
+            .filter(tasks::callback_url.is_not_null())
+            .select((tasks::id,
+                     tasks::callback_url.assume_not_null(),
+                     "pending".into_sql::<Text>(),
+                     0.into_sql::<Integer>(),
+                     current_time().into_sql::<Integer>())))
+        .into_columns((deliveries::task_id,
+                       deliveries::callback_url,
+                       deliveries::status,
+                       deliveries::attempts,
+                       deliveries::ts_next_attempt))
+        .on_conflict_do_nothing()
+        .execute(conn)
 }
 
 // todo: use api as a lib to avoid code duplication
 
-pub fn grab_lock(conn: &Connection) -> Result<MutexGuard<PgConnection>, String> {
+pub fn grab_lock(conn: &Connection) -> Result<MutexGuard<'_, PgConnection>, String> {
     match conn.lock() {
         Ok(conn) => Ok(conn),
         Err(e) => {
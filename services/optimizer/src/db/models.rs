@@ -12,9 +12,45 @@ pub struct Task {
     pub ts_submitted: i32,
     pub ts_started: Option<i32>,
     pub ts_completed: Option<i32>,
-    pub problem_capacity: i32,
-    pub problem_weights: Vec<Option<i32>>,
-    pub problem_values: Vec<Option<i32>>,
+    pub problem_capacity: i64,
+    pub problem_weights: Vec<Option<i64>>,
+    pub problem_values: Vec<Option<i64>>,
+    pub algorithm: String,
+    pub epsilon: Option<f64>,
+    pub initial_solution: Option<Vec<Option<i32>>>,
+    pub sensitivity: bool,
+    pub alternatives: Option<i32>,
+    pub optimal_only: bool,
+    pub tie_break: Option<String>,
+    // set if the task is a revision of another task
+    pub parent_id: Option<Uuid>,
+    pub removed_items: Option<Vec<Option<i32>>>,
+    // upper bound on the optimal value, known from the parent task
+    pub inherited_bound: Option<i64>,
+    // maximum deviations of the weights, of which at most gamma occur at once
+    pub problem_deviations: Option<Vec<Option<i64>>>,
+    pub gamma: Option<i32>,
+    // limits on the number of packed items, in total and per category
+    pub max_items: Option<i32>,
+    pub categories: Option<Vec<Option<String>>>,
+    pub category_names: Option<Vec<Option<String>>>,
+    pub category_limits: Option<Vec<Option<i32>>>,
+    // linear constraints on the packed items, with the coefficients of each
+    // constraint one after the other
+    pub constraint_coefficients: Option<Vec<Option<f64>>>,
+    pub constraint_bounds: Option<Vec<Option<f64>>>,
+    // only set once the task is cancelled, whether the best solution found
+    // until then is kept
+    pub keep_best: Option<bool>,
+    // who submitted the task, and labels to find it by
+    pub owner: Option<String>,
+    pub tags: Option<Vec<Option<String>>>,
+    // only set if the task was submitted with an idempotency key, along with
+    // the hash of the request
+    pub idempotency_key: Option<String>,
+    pub request_hash: Option<String>,
+    // where the knapsack is posted once the task has finished
+    pub callback_url: Option<String>,
 }
 
 #[derive(Queryable, Selectable, Insertable, Identifiable, Associations, Debug, Clone)]
@@ -23,18 +59,36 @@ pub struct Task {
 pub struct Solution {
     pub id: Uuid,
     pub packed_items: Vec<Option<i32>>,
-    pub total_value: i32,
+    pub total_value: i64,
     pub task_id: Uuid,
+    pub upper_bound: Option<i64>,
+    // 0 for the best solution of the task, 1 for the second best, ...
+    pub rank: i32,
 }
 
-/// PostgreSQL rows of type `integer[] NOT NULL` are allowed to have null entries
+#[derive(Queryable, Selectable, Insertable, Identifiable, Associations, Debug)]
+#[diesel(belongs_to(Task))]
+#[diesel(primary_key(task_id))]
+#[diesel(table_name = crate::db::schema::sensitivity_reports)]
+pub struct SensitivityReport {
+    pub task_id: Uuid,
+    pub optimal_value: i64,
+    pub capacity_price: f64,
+    pub reduced_costs: Vec<Option<f64>>,
+    pub value_thresholds: Vec<Option<i64>>,
+    pub extra_capacities: Vec<Option<i64>>,
+    pub capacity_increments: Vec<Option<i64>>,
+    pub capacity_values: Vec<Option<i64>>,
+}
+
+/// PostgreSQL rows of type `bigint[] NOT NULL` are allowed to have null entries
 /// in the array. I don't think it's possible to specify that all entries are
 /// non-null...
-pub fn map_arr(arr: &[i32]) -> Vec<Option<i32>> {
+pub fn map_arr<T: Copy>(arr: &[T]) -> Vec<Option<T>> {
     arr.iter().map(|i| Some(*i)).collect()
 }
 
-pub fn map_arr_inv(arr: &[Option<i32>]) -> Result<Vec<i32>, &str> {
+pub fn map_arr_inv<T: Copy>(arr: &[Option<T>]) -> Result<Vec<T>, &'static str> {
     if !arr.iter().all(|i| i.is_some()) {
         // This is an application error, as Rocket ensures all inputted vectors
         // for weights and values contain non-null entries
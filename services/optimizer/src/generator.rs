@@ -0,0 +1,278 @@
+//! Generates instances of the standard classes of Pisinger, see "Where are the
+//! hard knapsack problems?" (2005), to benchmark the solvers reproducibly.
+//!
+//! The weights and values of the items are drawn from `[1, R]`, and related
+//! to each other depending on the class:
+//!
+//! - `uncorrelated`: independently of each other.
+//! - `weakly_correlated`: values within `R/10` of the weight.
+//! - `strongly_correlated`: values `R/10` above the weight.
+//! - `inverse_strongly_correlated`: weights `R/10` above the value.
+//! - `almost_strongly_correlated`: values within `R/500` of the strongly
+//!   correlated value.
+//! - `subset_sum`: values equal to the weight.
+//! - `uncorrelated_spanner`, `weakly_correlated_spanner` and
+//!   `strongly_correlated_spanner`: span(2, 10), i.e. multiples of two small
+//!   items of the given class, whose weight and value are scaled down by 2/10,
+//!   by a factor between 1 and 10.
+//!
+//! As in Pisinger's series of instances, the h-th of H instances has a
+//! capacity of h / (H + 1) times the total weight.
+//!
+//! [Pisinger](https://doi.org/10.1016/j.cor.2004.03.002)
+
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// Number of spanner items, and the largest multiple of them
+const SPANNER_ITEMS: usize = 2;
+const SPANNER_MULTIPLIER: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Uncorrelated,
+    WeaklyCorrelated,
+    StronglyCorrelated,
+    InverseStronglyCorrelated,
+    AlmostStronglyCorrelated,
+    SubsetSum,
+    UncorrelatedSpanner,
+    WeaklyCorrelatedSpanner,
+    StronglyCorrelatedSpanner,
+}
+
+impl FromStr for Class {
+    type Err = String;
+
+    fn from_str(class: &str) -> Result<Self, String> {
+        use Class::*;
+        match class {
+            "uncorrelated" => Ok(Uncorrelated),
+            "weakly_correlated" => Ok(WeaklyCorrelated),
+            "strongly_correlated" => Ok(StronglyCorrelated),
+            "inverse_strongly_correlated" => Ok(InverseStronglyCorrelated),
+            "almost_strongly_correlated" => Ok(AlmostStronglyCorrelated),
+            "subset_sum" => Ok(SubsetSum),
+            "uncorrelated_spanner" => Ok(UncorrelatedSpanner),
+            "weakly_correlated_spanner" => Ok(WeaklyCorrelatedSpanner),
+            "strongly_correlated_spanner" => Ok(StronglyCorrelatedSpanner),
+            other => Err(format!("Unknown instance class {}", other)),
+        }
+    }
+}
+
+impl Class {
+    /// The class of the spanner items, if it's a spanner class
+    fn spanned(&self) -> Option<Class> {
+        match self {
+            Class::UncorrelatedSpanner => Some(Class::Uncorrelated),
+            Class::WeaklyCorrelatedSpanner => Some(Class::WeaklyCorrelated),
+            Class::StronglyCorrelatedSpanner => Some(Class::StronglyCorrelated),
+            _ => None,
+        }
+    }
+}
+
+/// The parameters of a series of instances
+#[derive(Debug, Clone, Copy)]
+pub struct Spec {
+    pub class: Class,
+    // number of items
+    pub n: usize,
+    // upper end of the range of the weights and values, R
+    pub range: u64,
+    pub seed: u64,
+}
+
+/// A generated problem, which serializes the same way as the problem of the
+/// API
+#[derive(Debug, Clone, Serialize)]
+pub struct Instance {
+    pub capacity: u64,
+    pub weights: Vec<u64>,
+    pub values: Vec<u64>,
+}
+
+impl Spec {
+    /// Fails unless the items are guaranteed to have positive weights and
+    /// values whose total fits into 63 bits, which is what the db stores
+    pub fn new(class: Class, n: usize, range: u64, seed: u64) -> Result<Self, String> {
+        // Weights and values are at most about 1.1 R, or 2.2 R for spanners
+        let largest = u128::from(range) * 3 + SPANNER_MULTIPLIER as u128;
+        let total = u128::try_from(n).map_err(|e| e.to_string())? * largest;
+        if n == 0 || range == 0 || total > i64::MAX as u128 {
+            return Err(format!("Can't generate {} items in the range [1, {}]", n, range));
+        }
+        Ok(Self { class, n, range, seed })
+    }
+
+    /// The h-th instance of a series of `series` instances, for h in
+    /// `1..=series`. Each instance only depends on the spec and h.
+    pub fn instance(&self, h: u32, series: u32) -> Instance {
+        let mut rng = SplitMix64(self.seed ^ u64::from(h).rotate_left(32));
+        let items: Vec<(u64, u64)> = match self.class.spanned() {
+            Some(class) => {
+                // Small spanner items, of which the items are multiples
+                let spanners: Vec<(u64, u64)> = (0..SPANNER_ITEMS)
+                    .map(|_| item(class, self.range, &mut rng))
+                    .map(|(w, v)| (scale_down(w), scale_down(v)))
+                    .collect();
+                (0..self.n)
+                    .map(|_| {
+                        let (w, v) = spanners[rng.below(SPANNER_ITEMS as u64) as usize];
+                        let multiple = rng.uniform(1, SPANNER_MULTIPLIER);
+                        (w * multiple, v * multiple)
+                    })
+                    .collect()
+            },
+            None => (0..self.n).map(|_| item(self.class, self.range, &mut rng)).collect(),
+        };
+
+        let total_weight: u128 = items.iter().map(|(w, _)| u128::from(*w)).sum();
+        let capacity = total_weight * u128::from(h) / (u128::from(series) + 1);
+        Instance {
+            capacity: capacity as u64,
+            weights: items.iter().map(|(w, _)| *w).collect(),
+            values: items.iter().map(|(_, v)| *v).collect(),
+        }
+    }
+
+    pub fn series(&self, series: u32) -> Vec<Instance> {
+        (1..=series).map(|h| self.instance(h, series)).collect()
+    }
+}
+
+/// The weight and value of an item of a class other than a spanner class
+fn item(class: Class, range: u64, rng: &mut SplitMix64) -> (u64, u64) {
+    let tenth = range / 10;
+    let weight = rng.uniform(1, range);
+    match class {
+        Class::Uncorrelated => (weight, rng.uniform(1, range)),
+        Class::WeaklyCorrelated => {
+            (weight, rng.uniform(weight.saturating_sub(tenth).max(1), weight + tenth))
+        },
+        Class::StronglyCorrelated => (weight, weight + tenth),
+        Class::InverseStronglyCorrelated => (weight + tenth, weight),
+        Class::AlmostStronglyCorrelated => {
+            let value = weight + tenth;
+            (weight, rng.uniform(value.saturating_sub(range / 500).max(1), value + range / 500))
+        },
+        Class::SubsetSum => (weight, weight),
+        _ => unreachable!("spanner classes are generated from spanner items"),
+    }
+}
+
+/// Scales the weight or value of a spanner item down by 2/m, rounding up
+fn scale_down(n: u64) -> u64 {
+    (2 * n).div_ceil(SPANNER_MULTIPLIER)
+}
+
+/// SplitMix64, a small pseudorandom generator whose sequence is fixed by its
+/// seed, so that instances are the same on any platform and with any version
+/// of the dependencies
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, n)`, where the bias is negligible for the small
+    /// ranges used here
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// A number in `[low, high]`
+    fn uniform(&mut self, low: u64, high: u64) -> u64 {
+        low + self.below(high - low + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLASSES: [Class; 9] = [
+        Class::Uncorrelated, Class::WeaklyCorrelated, Class::StronglyCorrelated,
+        Class::InverseStronglyCorrelated, Class::AlmostStronglyCorrelated, Class::SubsetSum,
+        Class::UncorrelatedSpanner, Class::WeaklyCorrelatedSpanner, Class::StronglyCorrelatedSpanner,
+    ];
+
+    /// The range of the weights of a class
+    fn weights(class: Class, range: u64) -> (u64, u64) {
+        match class {
+            Class::InverseStronglyCorrelated => (1 + range / 10, range + range / 10),
+            _ if class.spanned().is_some() => (1, SPANNER_MULTIPLIER * scale_down(range)),
+            _ => (1, range),
+        }
+    }
+
+    #[test]
+    fn generates_the_items_of_each_class() {
+        let range = 1000;
+        for class in CLASSES {
+            let spec = Spec::new(class, 50, range, 7).unwrap();
+            let (lightest, heaviest) = weights(class, range);
+            for instance in spec.series(5) {
+                assert_eq!(instance.weights.len(), 50);
+                assert_eq!(instance.values.len(), 50);
+                assert!(instance.weights.iter().all(|w| (lightest..=heaviest).contains(w)),
+                        "{:?}: {:?}", class, instance.weights);
+                assert!(instance.values.iter().all(|v| *v > 0), "{:?}: {:?}", class, instance.values);
+            }
+        }
+    }
+
+    #[test]
+    fn correlates_values_with_weights() {
+        let tenth = 100;
+        let instance = |class| Spec::new(class, 50, 1000, 7).unwrap().instance(1, 1);
+        let pairs = |instance: Instance| instance.weights.into_iter().zip(instance.values).collect::<Vec<_>>();
+        assert!(pairs(instance(Class::WeaklyCorrelated)).iter().all(|(w, v)| w.abs_diff(*v) <= tenth));
+        assert!(pairs(instance(Class::StronglyCorrelated)).iter().all(|(w, v)| *v == w + tenth));
+        assert!(pairs(instance(Class::InverseStronglyCorrelated)).iter().all(|(w, v)| *w == v + tenth));
+        assert!(pairs(instance(Class::SubsetSum)).iter().all(|(w, v)| w == v));
+    }
+
+    #[test]
+    fn sets_the_capacity_to_a_fraction_of_the_total_weight() {
+        for class in CLASSES {
+            let spec = Spec::new(class, 50, 1000, 7).unwrap();
+            for (h, instance) in (1u64..).zip(spec.series(4)) {
+                let total: u64 = instance.weights.iter().sum();
+                // capacity = floor(h / 5 * total)
+                assert!(instance.capacity * 5 <= h * total, "{:?} {}", class, h);
+                assert!((instance.capacity + 1) * 5 > h * total, "{:?} {}", class, h);
+            }
+        }
+    }
+
+    #[test]
+    fn depends_only_on_the_spec() {
+        for class in CLASSES {
+            let spec = Spec::new(class, 20, 1000, 42).unwrap();
+            let other = Spec { seed: 43, ..spec };
+            for h in 1..=3 {
+                assert_eq!(spec.instance(h, 3).weights, spec.instance(h, 3).weights);
+                assert_eq!(spec.instance(h, 3).values, spec.instance(h, 3).values);
+                assert_ne!(spec.instance(h, 3).weights, other.instance(h, 3).weights, "{:?}", class);
+            }
+        }
+        // The sequence is fixed, whatever the platform
+        let mut rng = SplitMix64(0);
+        assert_eq!(rng.next(), 0xe220a8397b1dcdaf);
+    }
+
+    #[test]
+    fn rejects_specs_which_dont_fit() {
+        assert!(Spec::new(Class::Uncorrelated, 0, 1000, 0).is_err());
+        assert!(Spec::new(Class::Uncorrelated, 10, 0, 0).is_err());
+        assert!(Spec::new(Class::Uncorrelated, 1 << 20, u64::MAX >> 10, 0).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+//! The optimizer service, which solves the tasks submitted to the API, and the
+//! tools to generate instances for it.
+
+pub mod db;
+pub mod generator;
+pub mod solver;
+pub mod verify;
+
+/// Gets the environment variable `name`, panicking if it's not set
+pub fn get_var(name: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| panic!("{} not set", name))
+}
@@ -2,15 +2,20 @@ use futures_lite::stream::StreamExt;
 use lapin::{message::Delivery, options::BasicAckOptions};
 use uuid::Uuid;
 
-use std::{sync::Arc, thread};
+use std::{sync::Arc, thread, time};
+use std::sync::mpsc::{self, RecvTimeoutError};
+
+use optimizer::{db, solver, verify};
 
-mod db;
 mod queue;
-mod solver;
 
 struct Connections {
     queue_consumer: lapin::Consumer,
+    // The channel and connection are never read, but must be kept alive for
+    // as long as the consumer is in use
+    #[allow(dead_code)]
     queue_channel: lapin::Channel,
+    #[allow(dead_code)]
     queue_conn: lapin::Connection,
     db_conn: db::Connection,
 }
@@ -31,7 +36,7 @@ async fn main() -> ! {
     loop {
         if let Some(delivery) = connections.queue_consumer.next().await {
             if let Ok(msg) = delivery {
-                match consume(msg, &mut connections).await {
+                match consume(msg, &connections).await {
                     Ok(()) => println!("yay, consumed a message"),
                     Err(e) => eprintln!("{}", e),
                 }
@@ -46,6 +51,9 @@ async fn main() -> ! {
 
 use backoff::{retry, ExponentialBackoff, Error};
 
+/// How often a running task is checked for having been cancelled
+const CANCELLATION_POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
 async fn consume(msg: Delivery, connections: &Connections) -> Result<(), String> {
     let data = &msg.data.clone();
     let data = map_err_to_string(std::str::from_utf8(data))?;
@@ -55,6 +63,11 @@ async fn consume(msg: Delivery, connections: &Connections) -> Result<(), String>
 
     map_err_to_string(msg.ack(BasicAckOptions::default()).await)?;
 
+    // The task may have been cancelled before it was started
+    let Some(task) = task else {
+        println!("Skipping task {}, which is no longer submitted", task_id);
+        return Ok(());
+    };
 
     // Cloned the reference counted database mutex, so it can be moved
     // into the new thread
@@ -62,14 +75,85 @@ async fn consume(msg: Delivery, connections: &Connections) -> Result<(), String>
 
     // Spawn a new thread to compute the solution and update the db
     thread::spawn(move || {
-        let solution = solver::solve(&task);
-        if let Ok(solution) = solution {
-            retry(ExponentialBackoff::default(), || {
-                db::insert_solution(solution.clone(), &db_conn)
+        // The watcher stops once `solved` is dropped
+        let cancellation = solver::Cancellation::default();
+        let (solved, watched) = mpsc::channel::<()>();
+        watch(task.id, cancellation.clone(), watched, Arc::clone(&db_conn));
+
+        // The service takes at least a second per task, which the solvers
+        // themselves don't, so that they can be benchmarked
+        thread::sleep(time::Duration::from_millis(1000));
+
+        // Progress is only informative, so failing to report it isn't retried
+        let reporter = {
+            let db_conn = Arc::clone(&db_conn);
+            let task_id = task.id;
+            solver::Reporter::new(move |progress| {
+                if let Err(e) = db::notify_progress(task_id, &progress, &db_conn) {
+                    eprintln!("Could not report progress of task {}: {}", task_id, e);
+                }
+            })
+        };
+
+        // Solutions are only stored if they are consistent with the task
+        let solutions = solver::solve(&task, &cancellation, &reporter).and_then(|solutions| {
+            for solution in &solutions {
+                verify::verify(&task, solution)?;
+            }
+            Ok(solutions)
+        });
+
+        // The report is about the best solution, which is ranked first. It's
+        // analysed while the task is still watched, so that cancelling the
+        // task stops the analysis too.
+        let report = match &solutions {
+            Ok(solutions) if task.sensitivity => {
+                Some(solver::analyse(&task, &solutions[0], &cancellation))
+            },
+            _ => None,
+        };
+        drop(solved);
+
+        // The task may also have been cancelled after it was last watched
+        let cancelled = retry(ExponentialBackoff::default(), || {
+            db::get_cancellation(task.id, &db_conn).map_err(Error::transient)
+        }).expect("Failed to read task status from db");
+        match (cancelled, solutions) {
+            (Some(false), _) => println!("Task {} was cancelled", task.id),
+            (Some(true), Ok(solutions)) => retry(ExponentialBackoff::default(), || {
+                db::insert_solutions(&solutions, None, &db_conn)
                     .map_err(Error::transient)
-            }).expect("Failed to update db with solution")
-        } else {
-            eprintln!("Could not solve task {}", task.id)
+            }).expect("Failed to update db with solution"),
+            (Some(true), Err(e)) => {
+                eprintln!("Could not solve cancelled task {}: {}", task.id, e);
+                retry(ExponentialBackoff::default(), || {
+                    db::enqueue_delivery(task.id, &db_conn)
+                        .map_err(Error::transient)
+                }).expect("Failed to update db with delivery")
+            },
+            (None, Ok(solutions)) => {
+                // The report is stored along with the solutions, so that it's
+                // available once the task is completed
+                let report = match report {
+                    Some(Ok(report)) => Some(report),
+                    Some(Err(e)) => {
+                        eprintln!("Could not analyse task {}: {}", task.id, e);
+                        None
+                    },
+                    None => None,
+                };
+                retry(ExponentialBackoff::default(), || {
+                    db::insert_solutions(&solutions, report.as_ref(), &db_conn)
+                        .map_err(Error::transient)
+                }).expect("Failed to update db with solution")
+            },
+            (None, Err(e)) => {
+                eprintln!("Could not solve task {}: {}", task.id, e);
+                retry(ExponentialBackoff::default(), || {
+                    db::set_task_status_failed(task.id, &db_conn)
+                        .map_err(Error::transient)
+                }).expect("Failed to update db with task status")
+            },
         }
     });
 
@@ -77,6 +161,21 @@ async fn consume(msg: Delivery, connections: &Connections) -> Result<(), String>
     Ok(())
 }
 
+/// Cancels the solver of the task once the task is cancelled in the db, which
+/// is checked until the task is solved, i.e. the sender of `solved` is dropped
+fn watch(task_id: Uuid, cancellation: solver::Cancellation, solved: mpsc::Receiver<()>,
+         db_conn: db::Connection) {
+    thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = solved.recv_timeout(CANCELLATION_POLL_INTERVAL) {
+            match db::get_cancellation(task_id, &db_conn) {
+                Ok(Some(_)) => return cancellation.cancel(),
+                Ok(None) => {},
+                Err(e) => eprintln!("Could not check if task {} is cancelled: {}", task_id, e),
+            }
+        }
+    });
+}
+
 fn map_err_to_string<T, E>(res: Result<T, E>) -> Result<T, String>
 where
     E: std::fmt::Display,
@@ -86,8 +185,3 @@ where
         Err(e) => Err(e.to_string()),
     }
 }
-
-/// Gets the environment variable `name`, panicking if it's not set
-fn get_var(name: &str) -> String {
-    std::env::var(name).expect(&format!("{} not set", name))
-}
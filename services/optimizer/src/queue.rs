@@ -6,7 +6,7 @@ use lapin::options::BasicConsumeOptions;
 use backoff::{ExponentialBackoff, Error};
 use backoff::future::retry;
 
-use crate::get_var;
+use optimizer::get_var;
 
 const AMQP_EXCHANGE: &str = "";
 const AMQP_QUEUE_NAME: &str = "problem_submitted";
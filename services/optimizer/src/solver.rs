@@ -1,46 +1,263 @@
-//! This file is almost directly copied from
+//! Solvers for the 0-1 knapsack problem.
 //!
-//! https://raw.githubusercontent.com/innoave/genevo/master/examples/knapsack/main.rs
+//! The task decides which algorithm is used:
 //!
-//! --------------------------------------------------------------------------------
-//! The 0-1 knapsack problem example searches for the combination of items that
-//! sums up to the greatest possible value while the total weight is still below
-//! or equal the allowed weight of the knapsack.
+//! - `genetic`: A genetic algorithm (see `genetic.rs`), which finds good
+//!   solutions quickly but gives no guarantee about how good they are.
+//! - `fptas`: A fully polynomial-time approximation scheme (see `fptas.rs`),
+//!   which guarantees a solution within a factor (1 - epsilon) of the optimum.
+//! - `branch_and_bound`: An exact search (see `branch_and_bound.rs`), which
+//!   always finds the optimum, but may take exponential time to do so.
+//! - `milp`: The knapsack as a mixed integer linear program, solved by HiGHS
+//!   (see `milp.rs`). It's only available if the optimizer is built with the
+//!   `milp` feature.
 //!
-//! [knapsack problem](https://en.wikipedia.org/wiki/Knapsack_problem)
+//! If the task has an initial solution, then it's used as the starting point
+//! of the search, and the solvers never return anything worse. Revisions of
+//! other tasks start from the solution of their parent, and may also inherit
+//! an upper bound from it, which stops the search once it's reached.
+//!
+//! A task can also ask for alternatives, i.e. the k best distinct solutions or
+//! all optimal solutions up to a limit, which only the exact search can give.
+//! Likewise, only the exact search honors the tie break of a task, which
+//! decides which of several packings of the same value is returned.
+//!
+//! Robust tasks give each item a maximum deviation of its weight, of which at
+//! most gamma occur at once. A packing is then only feasible if it fits into
+//! the knapsack in the worst case, where the gamma items with the largest
+//! deviations are all heavier than their nominal weight. All solvers but the
+//! `fptas` support this.
+//!
+//! Tasks may also limit the number of packed items, in total and per category
+//! of items, which the `fptas` doesn't support either.
+//!
+//! Finally, tasks may add arbitrary linear constraints on the packed items,
+//! `sum a_i x_i <= b`, which only the `milp` solver supports.
+//!
+//! A running solver can be cancelled from another thread, after which it
+//! stops as soon as it notices, returning the best packing found so far. The
+//! genetic solver also reports its progress after each generation.
 
-use genevo::{operator::prelude::*, population::*, prelude::*, types::fmt::Display};
-use smallvec::SmallVec;
+mod branch_and_bound;
+mod fptas;
+mod genetic;
+#[cfg(feature = "milp")]
+mod milp;
+mod sensitivity;
 
-use core::time;
-use std::thread::sleep;
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use uuid::Uuid;
 
-use crate::db::models::{Solution, Task};
+use crate::db::models::{SensitivityReport, Solution, Task};
+
+include!("../../shared/constraint_tolerance.rs");
+
+/// How long the sensitivity analysis of a task may take, as it solves the
+/// problem about twice per item
+pub const ANALYSIS_BUDGET: Duration = Duration::from_secs(60);
+
+/// Asks a running solver to stop, shared between the thread solving the task
+/// and the one cancelling it. It may also stop the solver once a deadline
+/// has passed.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(AtomicOrdering::Relaxed)
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// The same cancellation, which also stops the solver once `budget` has
+    /// passed from now
+    pub fn with_budget(&self, budget: Duration) -> Self {
+        Self { cancelled: Arc::clone(&self.cancelled), deadline: Some(Instant::now() + budget) }
+    }
+}
 
-pub fn solve(task: &Task) -> Result<Solution, String> {
+/// How far a running solver has come
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub generation: u64,
+    // value of the best feasible packing found so far, if any, scaled like
+    // the values of the task
+    pub best_value: Option<i64>,
+    pub elapsed: Duration,
+}
+
+/// Receives the progress of a running solver, which is ignored by default
+#[derive(Clone, Default)]
+pub struct Reporter(Option<Arc<dyn Fn(Progress) + Send + Sync>>);
+
+impl Reporter {
+    pub fn new(report: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(report)))
+    }
+
+    pub fn report(&self, progress: Progress) {
+        if let Some(report) = &self.0 {
+            report(progress);
+        }
+    }
+}
+
+impl std::fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Reporter").field(&self.0.is_some()).finish()
+    }
+}
+
+/// The solutions of the task ranked by decreasing value, of which there's
+/// only one unless the task asks for alternatives. If the task is cancelled
+/// while it's solved, the solutions are the best found so far.
+pub fn solve(task: &Task, cancellation: &Cancellation, reporter: &Reporter) -> Result<Vec<Solution>, String> {
     use crate::db::models::map_arr;
 
-    sleep(time::Duration::from_millis(1000));
-    let problem = Problem::from_task(&task);
-    let res = old_main(problem);
-    res.map(|knapsack| {
-        Solution {
+    let problem = Problem {
+        cancellation: cancellation.clone(),
+        reporter: reporter.clone(),
+        ..Problem::from_task(task)?
+    };
+    let initial = problem.initial_solution.clone()
+        .map(|indices| Knapsack::from_indices(indices, &problem.given_items))
+        .transpose()?;
+
+    if task.alternatives.is_some() && task.algorithm != "branch_and_bound" {
+        return Err(format!("Alternatives can't be found by {}", task.algorithm));
+    }
+    if task.tie_break.is_some() && task.algorithm != "branch_and_bound" {
+        return Err(format!("Ties can't be broken by {}", task.algorithm));
+    }
+    if problem.gamma > 0 && task.algorithm == "fptas" {
+        return Err("fptas: robust problems aren't supported".to_string());
+    }
+    if problem.has_limits() && task.algorithm == "fptas" {
+        return Err("fptas: limits on the number of items aren't supported".to_string());
+    }
+    if problem.has_constraints() && task.algorithm != "milp" {
+        return Err(format!("{}: linear constraints aren't supported", task.algorithm));
+    }
+
+    let (mut knapsacks, upper_bound) = match task.algorithm.as_str() {
+        "genetic" => (vec![genetic::old_main(&problem)?], None),
+        "fptas" => {
+            let epsilon = task.epsilon.ok_or("fptas: epsilon not set")?;
+            let knapsack = fptas::solve(&problem, epsilon)?;
+            // The optimum is at most value / (1 - epsilon), but the bound
+            // from the LP relaxation may be tighter. Nothing is guaranteed
+            // if the solver was stopped early.
+            let guaranteed = (knapsack.value as f64 / (1.0 - epsilon)).floor() as i64;
+            let upper_bound = guaranteed.min(problem.upper_bound());
+            (vec![knapsack], Some(upper_bound).filter(|_| !cancellation.is_cancelled()))
+        },
+        "branch_and_bound" => {
+            let incumbent = match &initial {
+                Some(initial) => initial.clone(),
+                None => branch_and_bound::greedy(&problem)?,
+            };
+            let knapsacks = match task.alternatives {
+                Some(count) => {
+                    let count = usize::try_from(count).map_err(|e| e.to_string())?;
+                    branch_and_bound::solve_ranked(
+                        &problem, incumbent, count, task.optimal_only)?
+                },
+                None => vec![branch_and_bound::solve(&problem, incumbent)?],
+            };
+            (knapsacks, None)
+        },
+        #[cfg(feature = "milp")]
+        "milp" => (vec![milp::solve(&problem, initial.as_ref())?], None),
+        #[cfg(not(feature = "milp"))]
+        "milp" => return Err("milp: the optimizer was built without the milp feature".to_string()),
+        other => return Err(format!("Unknown algorithm {}", other)),
+    };
+
+    if let (Some(initial), [knapsack]) = (initial, &mut knapsacks[..]) {
+        if initial.value > knapsack.value {
+            *knapsack = initial;
+        }
+    }
+
+    knapsacks.into_iter()
+        .enumerate()
+        .map(|(rank, knapsack)| Ok(Solution {
             id: Uuid::new_v4(),
             packed_items: map_arr(&knapsack.indices.iter()
                                   .map(|i| *i as i32).collect::<Vec<i32>>()),
-            total_value: knapsack.value as i32,
+            total_value: knapsack.value,
             task_id: task.id,
-        }
-    })
+            upper_bound,
+            rank: i32::try_from(rank).map_err(|e| e.to_string())?,
+        }))
+        .collect()
 }
 
+/// Analyses how sensitive the solution is to changes of the problem, see
+/// `sensitivity.rs`. The analysis stops once it's cancelled or has run out of
+/// its budget, in which case there's no report.
+pub fn analyse(task: &Task, solution: &Solution, cancellation: &Cancellation)
+               -> Result<SensitivityReport, String> {
+    use crate::db::models::{map_arr, map_arr_inv};
+
+    let problem = Problem {
+        cancellation: cancellation.with_budget(ANALYSIS_BUDGET),
+        ..Problem::from_task(task)?
+    };
+    if problem.gamma > 0 {
+        return Err("Robust problems can't be analysed".to_string());
+    }
+    if problem.has_limits() {
+        return Err("Problems with limits on the number of items can't be analysed".to_string());
+    }
+    if problem.has_constraints() {
+        return Err("Problems with linear constraints can't be analysed".to_string());
+    }
+    let indices = map_arr_inv(&solution.packed_items)?.into_iter()
+        .map(|i| usize::try_from(i).map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    let knapsack = Knapsack::from_indices(indices, &problem.given_items)?;
+    let report = sensitivity::analyse(&problem, &knapsack)?;
+
+    let to_i64 = |n: u64| i64::try_from(n).map_err(|e| e.to_string());
+    Ok(SensitivityReport {
+        task_id: task.id,
+        optimal_value: report.optimal_value,
+        capacity_price: report.capacity_price,
+        reduced_costs: map_arr(&report.reduced_costs),
+        value_thresholds: report.value_thresholds,
+        extra_capacities: report.extra_capacities.into_iter()
+            .map(|c| c.map(to_i64).transpose())
+            .collect::<Result<_, _>>()?,
+        capacity_increments: report.capacity_increments.iter()
+            .map(|(capacity, _)| to_i64(*capacity).map(Some))
+            .collect::<Result<_, _>>()?,
+        capacity_values: report.capacity_increments.iter()
+            .map(|(_, value)| Some(*value))
+            .collect(),
+    })
+}
 
 #[derive(Debug, Clone)]
 struct Item {
-    value: i32,
-    weight: u32,
+    value: i64,
+    weight: u64,
+    // how much heavier than its weight the item may be
+    deviation: u64,
+    // index into the category limits of the problem, if it's limited
+    category: Option<usize>,
+    // coefficient of the item in each linear constraint of the problem
+    coefficients: Vec<f64>,
 }
 
 #[derive(Debug, Clone)]
@@ -49,11 +266,14 @@ struct GivenItems {
 }
 
 impl Item {
-    /// Panics if weight is negative
-    fn from(value: i32, weight: i32) -> Self {
+    /// Panics if weight or deviation is negative
+    fn from(value: i64, weight: i64, deviation: i64) -> Self {
         Self {
             value,
-            weight: weight as u32,
+            weight: u64::try_from(weight).expect("Negative weight"),
+            deviation: u64::try_from(deviation).expect("Negative deviation"),
+            category: None,
+            coefficients: vec![],
         }
     }
 }
@@ -64,8 +284,8 @@ impl From<Vec<Item>> for GivenItems {
     }
 }
 
-/// The phenotype
-#[derive(Debug)]
+/// A packed knapsack, as computed by one of the solvers
+#[derive(Debug, Clone)]
 struct Knapsack {
     items: Vec<Item>,
     value: i64,
@@ -73,191 +293,365 @@ struct Knapsack {
     indices: Vec<usize>,
 }
 
-/// The genotype
-type Selection = SmallVec<[bool; 16]>;
-
-/// How do the genes of the genotype show up in the phenotype
-trait AsPhenotype {
-    fn as_knapsack(&self, given_items: &GivenItems) -> Knapsack;
-}
+impl Knapsack {
+    /// Packs the items with the given indices, failing if any of them is out
+    /// of range or their total weight or value overflows
+    fn from_indices(indices: Vec<usize>, given_items: &GivenItems) -> Result<Self, String> {
+        let items: Vec<Item> = indices.iter()
+            .map(|i| given_items.list.get(*i).cloned()
+                 .ok_or(format!("Packed item {} out of range", i)))
+            .collect::<Result<_, _>>()?;
+        let (weight, value) = total(&items)
+            .ok_or("Total weight or value of knapsack overflows")?;
 
-impl AsPhenotype for Selection {
-    fn as_knapsack(&self, given_items: &GivenItems) -> Knapsack {
-        let mut indices = vec![];
-        let items: Vec<Item> = self
-            .into_iter()
-            .enumerate()
-            .filter_map(|(index, selected)| {
-                if *selected {
-                    indices.push(index);
-                    Some(given_items.list[index].clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        let value = items.iter().map(|i| i.value as i64).sum::<i64>();
-        let weight = items.iter().map(|i| i.weight as u64).sum::<u64>();
-
-        Knapsack {
+        Ok(Knapsack {
             items,
             value,
             weight,
             indices,
-        }
+        })
     }
 }
 
+/// Sums up the weights and values of the items, or returns `None` on overflow
+fn total<'a>(items: impl IntoIterator<Item = &'a Item>) -> Option<(u64, i64)> {
+    items.into_iter().try_fold((0u64, 0i64), |(weight, value), item| {
+        Some((weight.checked_add(item.weight)?, value.checked_add(item.value)?))
+    })
+}
+
 /// The problem definition
 #[derive(Debug, Clone)]
 struct Problem {
     given_items: GivenItems,
     allowed_weight: u64,
     highest_possible_fitness: i64,
+    // indices of the packed items of a known feasible solution
+    initial_solution: Option<Vec<usize>>,
+    tie_break: Option<TieBreak>,
+    // upper bound on the optimal value known beforehand
+    known_bound: Option<i64>,
+    // number of items whose weights may deviate at once, zero unless robust
+    gamma: u32,
+    max_items: Option<usize>,
+    // limits on the number of packed items of each category
+    category_limits: Vec<usize>,
+    // right-hand sides of the linear constraints, whose coefficients are
+    // stored with the items
+    constraint_bounds: Vec<f64>,
+    cancellation: Cancellation,
+    reporter: Reporter,
+}
+
+/// The number of packed items, in total and per category, to enforce the
+/// limits of the problem
+#[derive(Debug, Clone)]
+struct Counts {
+    items: usize,
+    per_category: Vec<usize>,
+}
+
+impl Counts {
+    fn new(problem: &Problem) -> Self {
+        Self { items: 0, per_category: vec![0; problem.category_limits.len()] }
+    }
+
+    /// Whether one more item can be packed without exceeding any limit
+    fn admits(&self, problem: &Problem, item: &Item) -> bool {
+        problem.max_items.map_or(true, |max| self.items < max)
+            && item.category.map_or(true, |c| self.per_category[c] < problem.category_limits[c])
+    }
+
+    fn add(&mut self, item: &Item) {
+        self.items += 1;
+        if let Some(c) = item.category {
+            self.per_category[c] += 1;
+        }
+    }
+
+    fn remove(&mut self, item: &Item) {
+        self.items -= 1;
+        if let Some(c) = item.category {
+            self.per_category[c] -= 1;
+        }
+    }
+}
+
+/// Secondary objective deciding between packings of the same value
+#[derive(Debug, Clone, Copy)]
+enum TieBreak {
+    LeastWeight,
+    FewestItems,
+    Lexicographic,
+}
+
+impl TieBreak {
+    fn from_str(tie_break: &str) -> Result<Self, String> {
+        match tie_break {
+            "least_weight" => Ok(TieBreak::LeastWeight),
+            "fewest_items" => Ok(TieBreak::FewestItems),
+            "lexicographic" => Ok(TieBreak::Lexicographic),
+            other => Err(format!("Unknown tie break {}", other)),
+        }
+    }
+
+    /// Orders packings with the preferred one first. Packings which are
+    /// equal by the secondary objective are ordered by their indices, so
+    /// that the order is total.
+    fn cmp(&self, a: &Knapsack, b: &Knapsack) -> Ordering {
+        let secondary = match self {
+            TieBreak::LeastWeight => a.weight.cmp(&b.weight),
+            TieBreak::FewestItems => a.indices.len().cmp(&b.indices.len()),
+            TieBreak::Lexicographic => Ordering::Equal,
+        };
+        secondary.then_with(|| a.indices.cmp(&b.indices))
+    }
 }
 
 impl Problem {
-    pub fn new(allowed_weight: u64, given_items: GivenItems) -> Self {
+    /// Fails if the total value of all items overflows, which guarantees
+    /// that the value of any packing can be computed without overflow
+    pub fn new(allowed_weight: u64, given_items: GivenItems) -> Result<Self, String> {
         let highest_possible_fitness = given_items
             .list
             .iter()
-            .map(|item| i64::from(item.value))
-            .sum::<i64>();
-        Self {
+            .try_fold(0i64, |acc, item| acc.checked_add(item.value))
+            .ok_or("Total value of items overflows")?;
+        Ok(Self {
             given_items,
             allowed_weight,
             highest_possible_fitness,
-        }
+            initial_solution: None,
+            tie_break: None,
+            known_bound: None,
+            gamma: 0,
+            max_items: None,
+            category_limits: vec![],
+            constraint_bounds: vec![],
+            cancellation: Cancellation::default(),
+            reporter: Reporter::default(),
+        })
     }
 
-    pub fn from_task(task: &Task) -> Self {
+    pub fn from_task(task: &Task) -> Result<Self, String> {
         use crate::db::models::map_arr_inv as f;
-        let capacity = task.problem_capacity as u64;
-        let items: Vec<Item> = f(&task.problem_values)
-            .unwrap().iter()
-            .zip(f(&task.problem_weights).unwrap())
-            .map(|(v, w)| Item::from(*v, w))
+        let capacity = u64::try_from(task.problem_capacity)
+            .map_err(|e| e.to_string())?;
+        let weights = f(&task.problem_weights)?;
+        let deviations = match &task.problem_deviations {
+            Some(deviations) => f(deviations)?,
+            None => vec![0; weights.len()],
+        };
+        let mut items: Vec<Item> = f(&task.problem_values)?.iter()
+            .zip(weights)
+            .zip(deviations)
+            .map(|((v, w), d)| Item::from(*v, w, d))
             .collect();
 
-        Self::new(capacity, GivenItems::from(items))
-    }
-}
+        // Items of a category without a limit are treated as uncategorised
+        let names = task.category_names.clone().unwrap_or_default();
+        let category_limits = f(task.category_limits.as_deref().unwrap_or_default())?
+            .into_iter()
+            .map(|limit| usize::try_from(limit).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(categories) = &task.categories {
+            for (item, category) in items.iter_mut().zip(categories) {
+                item.category = category.as_ref()
+                    .and_then(|c| names.iter().position(|name| name.as_ref() == Some(c)));
+            }
+        }
 
-/// The fitness function for `Selection`
-impl<'a> FitnessFunction<Selection, i64> for &'a Problem {
-    fn fitness_of(&self, selection: &Selection) -> i64 {
-        let (total_weight, total_value) = selection
-            .iter()
-            .enumerate()
-            .filter_map(|(index, selected)| {
-                if *selected {
-                    let item = &self.given_items.list[index];
-                    Some((u64::from(item.weight), i64::from(item.value)))
-                } else {
-                    None
-                }
+        // The coefficients are stored one constraint after the other
+        let constraint_bounds = f(task.constraint_bounds.as_deref().unwrap_or_default())?;
+        let coefficients = f(task.constraint_coefficients.as_deref().unwrap_or_default())?;
+        if coefficients.len() != items.len() * constraint_bounds.len() {
+            return Err("Number of constraint coefficients doesn't match the items".to_string());
+        }
+        let n = items.len();
+        for (i, item) in items.iter_mut().enumerate() {
+            item.coefficients = coefficients.iter()
+                .skip(i)
+                .step_by(n.max(1))
+                .copied()
+                .collect();
+        }
+
+        let initial_solution = task.initial_solution.as_deref()
+            .map(|indices| -> Result<Vec<usize>, String> {
+                f(indices)?.into_iter()
+                    .map(|i| usize::try_from(i)
+                         .ok().filter(|i| *i < items.len())
+                         .ok_or(format!("Initial solution item {} out of range", i)))
+                    .collect()
             })
-            .fold((0, 0), |(acc_weight, acc_value), (weight, value)| {
-                (acc_weight + weight, acc_value + value)
-            });
-        if total_weight > self.allowed_weight {
-            0
-        } else {
-            total_value
+            .transpose()?;
+
+        let tie_break = task.tie_break.as_deref()
+            .map(TieBreak::from_str)
+            .transpose()?;
+
+        Ok(Self {
+            initial_solution,
+            tie_break,
+            known_bound: task.inherited_bound,
+            gamma: task.gamma.map(u32::try_from).transpose()
+                .map_err(|e| e.to_string())?
+                .unwrap_or(0),
+            max_items: task.max_items.map(usize::try_from).transpose()
+                .map_err(|e| e.to_string())?,
+            category_limits,
+            constraint_bounds,
+            ..Self::new(capacity, GivenItems::from(items))?
+        })
+    }
+
+    /// The worst case weight of items with a total nominal weight of
+    /// `weight` and the given deviations, or `None` on overflow
+    fn worst_case_weight(&self, weight: u64, deviations: &[u64]) -> Option<u64> {
+        if self.gamma == 0 {
+            return Some(weight);
         }
+        // Only the gamma largest deviations count
+        let mut deviations = deviations.to_vec();
+        let gamma = (self.gamma as usize).min(deviations.len());
+        if gamma < deviations.len() {
+            deviations.select_nth_unstable_by(gamma, |a, b| b.cmp(a));
+        }
+        deviations[..gamma].iter().try_fold(weight, |acc, d| acc.checked_add(*d))
     }
 
-    fn average(&self, values: &[i64]) -> i64 {
-        (values.iter().sum::<i64>() as f32 / values.len() as f32 + 0.5).floor() as i64
+    fn has_limits(&self) -> bool {
+        self.max_items.is_some() || !self.category_limits.is_empty()
     }
 
-    fn highest_possible_fitness(&self) -> i64 {
-        self.highest_possible_fitness
+    fn has_constraints(&self) -> bool {
+        !self.constraint_bounds.is_empty()
     }
 
-    fn lowest_possible_fitness(&self) -> i64 {
-        0
+    fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
     }
-}
 
-fn old_main(problem: Problem) -> Result<Knapsack, String> {
-
-    let initial_population: Population<Selection> = build_population()
-        .with_genome_builder(BinaryEncodedGenomeBuilder::new(
-            problem.given_items.list.len(),
-        ))
-        .of_size(400)
-        .uniform_at_random();
-
-    let mut knapsack_sim = simulate(
-        genetic_algorithm()
-            .with_evaluation(&problem)
-            .with_selection(MaximizeSelector::new(0.85, 12))
-            .with_crossover(SinglePointCrossBreeder::new())
-            .with_mutation(RandomValueMutator::new(0.2, false, true))
-            .with_reinsertion(ElitistReinserter::new(&problem, false, 0.85))
-            .with_initial_population(initial_population)
-            .build(),
-    )
-    .until(GenerationLimit::new(20))
-    .build();
-
-    loop {
-        let result = knapsack_sim.step();
-
-        match result {
-            Ok(SimResult::Intermediate(step)) => {
-                let evaluated_population = step.result.evaluated_population;
-                let best_solution = step.result.best_solution;
-                println!(
-                    "step: generation: {}, average_fitness: {}, \
-                     best fitness: {}, duration: {}, processing_time: {}",
-                    step.iteration,
-                    evaluated_population.average_fitness(),
-                    best_solution.solution.fitness,
-                    step.duration.fmt(),
-                    step.processing_time.fmt(),
-                );
-                let knapsack = best_solution
-                    .solution
-                    .genome
-                    .as_knapsack(&problem.given_items);
-                println!(
-                    "      Knapsack: number of items: {}, total value: {}, total weight: {}",
-                    knapsack.items.len(),
-                    knapsack.value,
-                    knapsack.weight
-                );
-            },
-            Ok(SimResult::Final(step, processing_time, duration, stop_reason)) => {
-                let best_solution = step.result.best_solution;
-                println!("{}", stop_reason);
-                println!(
-                    "Final result after {}: generation: {}, \
-                     best solution with fitness {} found in generation {}, processing_time: {}",
-                    duration.fmt(),
-                    step.iteration,
-                    best_solution.solution.fitness,
-                    best_solution.generation,
-                    processing_time.fmt(),
-                );
-                let knapsack = best_solution
-                    .solution
-                    .genome
-                    .as_knapsack(&problem.given_items);
-                println!(
-                    "      Knapsack: number of items: {}, total value: {}, total weight: {}",
-                    knapsack.items.len(),
-                    knapsack.value,
-                    knapsack.weight
-                );
-                return Ok(knapsack);
-            },
-            Err(error) => {
-                eprintln!("{}", error);
-                return Err(error.to_string())
-            },
+    /// Whether the items can be packed together, i.e. fit into the knapsack
+    /// in the worst case without exceeding any limit on their number or
+    /// violating any linear constraint
+    fn fits<'a>(&self, items: impl IntoIterator<Item = &'a Item>) -> bool {
+        let items: Vec<&Item> = items.into_iter().collect();
+        let mut counts = Counts::new(self);
+        for item in &items {
+            if !counts.admits(self, item) {
+                return false;
+            }
+            counts.add(item);
         }
+
+        let violates = |(j, bound): (usize, &f64)| {
+            let sum: f64 = items.iter().map(|item| item.coefficients[j]).sum();
+            sum > bound + CONSTRAINT_TOLERANCE * bound.abs().max(1.0)
+        };
+        if self.constraint_bounds.iter().enumerate().any(violates) {
+            return false;
+        }
+
+        let deviations: Vec<u64> = items.iter().map(|item| item.deviation).collect();
+        total(items)
+            .and_then(|(weight, _)| self.worst_case_weight(weight, &deviations))
+            .is_some_and(|weight| weight <= self.allowed_weight)
+    }
+
+    /// Whether packing `a` is preferred over packing `b`, i.e. has a higher
+    /// value, or the same value and comes first by the tie break
+    fn prefers(&self, a: &Knapsack, b: &Knapsack) -> bool {
+        match (a.value.cmp(&b.value), self.tie_break) {
+            (Ordering::Equal, Some(tie_break)) => tie_break.cmp(a, b) == Ordering::Less,
+            (ordering, _) => ordering == Ordering::Greater,
+        }
+    }
+
+    /// The same problem, but where item `i` is worthless, so that no solver
+    /// will pack it
+    fn without(&self, i: usize) -> Self {
+        let mut problem = self.clone();
+        problem.given_items.list[i].value = 0;
+        problem
+    }
+
+    /// The Dantzig upper bound, i.e. the optimal value of the LP relaxation
+    /// rounded down, which no packing can exceed. A bound known beforehand is
+    /// used instead if it's tighter.
+    fn upper_bound(&self) -> i64 {
+        let mut items: Vec<&Item> = self.given_items.list.iter()
+            .filter(|item| item.value > 0)
+            .collect();
+        // Sort by decreasing value per unit of weight, with weightless items
+        // first. The products are computed in 128 bits, so they can't overflow.
+        items.sort_by(|a, b| {
+            (i128::from(b.value) * i128::from(a.weight))
+                .cmp(&(i128::from(a.value) * i128::from(b.weight)))
+        });
+
+        // The bound never exceeds the total value of all items, so it can't
+        // overflow
+        let mut capacity = self.allowed_weight;
+        let mut bound = 0;
+        for item in items {
+            if item.weight <= capacity {
+                capacity -= item.weight;
+                bound += item.value;
+            } else {
+                // Fill the remaining capacity with a fraction of the
+                // critical item
+                bound += (i128::from(item.value) * i128::from(capacity)
+                          / i128::from(item.weight)) as i64;
+                break;
+            }
+        }
+        self.known_bound.map_or(bound, |known| known.min(bound))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{Class, Spec};
+
+    /// Small instances of every class, few enough items to try every packing
+    pub(super) fn small_problems() -> Vec<Problem> {
+        use Class::*;
+        let classes = [Uncorrelated, WeaklyCorrelated, StronglyCorrelated,
+                       InverseStronglyCorrelated, AlmostStronglyCorrelated, SubsetSum,
+                       UncorrelatedSpanner, WeaklyCorrelatedSpanner, StronglyCorrelatedSpanner];
+        classes.into_iter()
+            .flat_map(|class| Spec::new(class, 10, 1000, 7).unwrap().series(3))
+            .map(|instance| {
+                let items: Vec<Item> = instance.values.iter().zip(&instance.weights)
+                    .map(|(v, w)| Item::from(*v as i64, *w as i64, 0))
+                    .collect();
+                Problem::new(instance.capacity, GivenItems::from(items)).unwrap()
+            })
+            .collect()
+    }
+
+    /// The values of all feasible packings, best first
+    pub(super) fn brute_force(problem: &Problem) -> Vec<i64> {
+        let items = &problem.given_items.list;
+        let mut values: Vec<i64> = (0..1u32 << items.len())
+            .map(|mask| (0..items.len())
+                 .filter(|i| mask & (1 << i) != 0)
+                 .map(|i| &items[i])
+                 .collect::<Vec<_>>())
+            .filter(|packed| problem.fits(packed.iter().copied()))
+            .map(|packed| packed.iter().map(|item| item.value).sum())
+            .collect();
+        values.sort_by(|a, b| b.cmp(a));
+        values
+    }
+
+    #[test]
+    fn packs_only_items_in_range() {
+        let problem = &small_problems()[0];
+        let n = problem.given_items.list.len();
+        assert_eq!(Knapsack::from_indices(vec![0, n - 1], &problem.given_items).unwrap().indices, [0, n - 1]);
+        assert!(Knapsack::from_indices(vec![0, n], &problem.given_items).is_err());
     }
 }
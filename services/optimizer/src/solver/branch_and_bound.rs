@@ -0,0 +1,310 @@
+//! Exact depth-first branch and bound for the 0-1 knapsack problem.
+//!
+//! The items are considered in order of decreasing value per unit of weight,
+//! always trying to pack an item before leaving it out. A branch is cut off
+//! as soon as the optimal value of its LP relaxation (the Dantzig bound)
+//! can't beat the best packing found so far, the incumbent.
+//!
+//! The search is started with an incumbent, either the initial solution of
+//! the problem or a greedy packing, which serves as a lower bound. The
+//! result is guaranteed to be optimal.
+//!
+//! The same search can also rank the packings, keeping the k best distinct
+//! ones instead of only the best, or all optimal packings up to a limit. A
+//! branch is then only cut off once it can't beat the worst packing kept.
+//!
+//! For robust problems, an item is only packed if the packing still fits in
+//! the worst case. The worst case weight of the packed items is at least
+//! their nominal weight, so the bound stays valid when it's computed from the
+//! remaining capacity in the worst case.
+//!
+//! Limits on the number of packed items are enforced the same way, while the
+//! bound ignores them.
+//!
+//! If the problem has a tie break, packings of the same value are ordered by
+//! it, and branches which can only tie with the packings kept are searched
+//! too. Items without any value are never packed.
+
+use super::{Counts, Knapsack, Problem};
+
+pub fn solve(problem: &Problem, incumbent: Knapsack) -> Result<Knapsack, String> {
+    // An incumbent which reaches the upper bound is optimal, unless it may
+    // have to give way to another optimal packing
+    if problem.tie_break.is_none() && incumbent.value >= problem.upper_bound() {
+        return Ok(incumbent);
+    }
+
+    let ranking = Ranking::new(problem, 1, false, incumbent.value);
+    let best = search(problem, ranking)?.into_iter().next();
+    match best {
+        Some(best) if problem.prefers(&best, &incumbent) => Ok(best),
+        _ => Ok(incumbent),
+    }
+}
+
+/// The `count` best distinct packings ranked by decreasing value, or if
+/// `optimal_only` is set, up to `count` packings which are all optimal
+pub fn solve_ranked(problem: &Problem, incumbent: Knapsack, count: usize, optimal_only: bool)
+                    -> Result<Vec<Knapsack>, String> {
+    // Only optimal packings can use the incumbent to cut off branches, as
+    // the k best packings may be worse than it
+    let floor = if optimal_only { incumbent.value } else { -1 };
+    let found = search(problem, Ranking::new(problem, count, optimal_only, floor))?;
+    if found.is_empty() {
+        return Ok(vec![incumbent]);
+    }
+    Ok(found)
+}
+
+/// Runs the search, returning the packings kept by the ranking
+fn search(problem: &Problem, mut ranking: Ranking) -> Result<Vec<Knapsack>, String> {
+    let search = Search::new(problem);
+
+    let n = search.order.len();
+    let mut taken = vec![false; n];
+    let mut level = 0;
+    // The worst case weight of the packed items, and what it was before each
+    // of them was packed. The nominal weight and the deviations are tracked
+    // separately to compute it.
+    let mut weight: u64 = 0;
+    let mut previous_weights = vec![];
+    let mut nominal_weight: u64 = 0;
+    let mut deviations = vec![];
+    let mut counts = Counts::new(problem);
+    let mut value: i64 = 0;
+
+    // A cancelled search keeps what it has found so far
+    while !problem.is_cancelled() {
+        let backtrack = if level == n {
+            if !ranking.prunes(value) {
+                ranking.insert(search.knapsack(&taken)?);
+            }
+            true
+        } else {
+            ranking.prunes(search.bound(level, weight, value))
+        };
+
+        if backtrack {
+            // Leave out the most recently packed item, whose other branch
+            // hasn't been explored yet
+            match (0..level).rev().find(|j| taken[*j]) {
+                Some(j) => {
+                    let item = search.item(j);
+                    taken[j] = false;
+                    weight = previous_weights.pop().unwrap_or(0);
+                    nominal_weight -= item.weight;
+                    deviations.pop();
+                    counts.remove(item);
+                    value -= item.value;
+                    level = j + 1;
+                },
+                None => break,
+            }
+        } else {
+            let item = search.item(level);
+            // Both weights are at most the allowed weight, so they can't overflow
+            deviations.push(item.deviation);
+            let packed_weight = problem.worst_case_weight(nominal_weight + item.weight, &deviations)
+                .filter(|w| *w <= problem.allowed_weight && counts.admits(problem, item));
+            taken[level] = packed_weight.is_some();
+            if let Some(packed_weight) = packed_weight {
+                previous_weights.push(weight);
+                weight = packed_weight;
+                nominal_weight += item.weight;
+                counts.add(item);
+                value += item.value;
+            } else {
+                deviations.pop();
+            }
+            level += 1;
+        }
+    }
+
+    Ok(ranking.found)
+}
+
+/// The packings kept during the search, ranked by decreasing value and then
+/// by the tie break of the problem
+struct Ranking<'a> {
+    problem: &'a Problem,
+    count: usize,
+    optimal_only: bool,
+    // packings must be better than this to be kept, or at least as good if
+    // only optimal packings are kept
+    floor: i64,
+    found: Vec<Knapsack>,
+}
+
+impl<'a> Ranking<'a> {
+    fn new(problem: &'a Problem, count: usize, optimal_only: bool, floor: i64) -> Self {
+        Self { problem, count, optimal_only, floor, found: vec![] }
+    }
+
+    fn is_full(&self) -> bool {
+        self.found.len() >= self.count
+    }
+
+    /// Whether a branch whose packings are worth at most `bound` can be cut off
+    fn prunes(&self, bound: i64) -> bool {
+        // With a tie break, a packing of the same value may still be preferred
+        let ties = self.problem.tie_break.is_some();
+        let cut = |threshold: i64| bound < threshold || (!ties && bound == threshold);
+        if self.optimal_only {
+            let best = self.found.first().map_or(self.floor, |k| k.value);
+            bound < best || (self.is_full() && cut(best))
+        } else {
+            let worst = self.found.last()
+                .filter(|_| self.is_full())
+                .map_or(self.floor, |k| k.value);
+            cut(worst)
+        }
+    }
+
+    /// Keeps the packing if it ranks high enough, which must have been
+    /// checked to not be pruned
+    fn insert(&mut self, knapsack: Knapsack) {
+        if self.optimal_only && self.found.first().is_some_and(|k| knapsack.value > k.value) {
+            self.found.clear();
+        }
+        let position = self.found
+            .partition_point(|k| !self.problem.prefers(&knapsack, k));
+        self.found.insert(position, knapsack);
+        self.found.truncate(self.count);
+    }
+}
+
+/// Packs the items in order of decreasing value per unit of weight, as long as
+/// they fit, which gives a reasonable incumbent to start the search from
+pub fn greedy(problem: &Problem) -> Result<Knapsack, String> {
+    let search = Search::new(problem);
+    let mut indices = vec![];
+    let mut weight: u64 = 0;
+    let mut deviations = vec![];
+    let mut counts = Counts::new(problem);
+    for (j, index) in search.order.iter().enumerate() {
+        let item = search.item(j);
+        deviations.push(item.deviation);
+        let fits = problem.worst_case_weight(weight + item.weight, &deviations)
+            .is_some_and(|w| w <= problem.allowed_weight && counts.admits(problem, item));
+        if fits {
+            weight += item.weight;
+            counts.add(item);
+            indices.push(*index);
+        } else {
+            deviations.pop();
+        }
+    }
+    indices.sort();
+    Knapsack::from_indices(indices, &problem.given_items)
+}
+
+/// The items worth considering, sorted for the search
+struct Search<'a> {
+    problem: &'a Problem,
+    order: Vec<usize>,
+    // prefix sums of the weights and values in the order of the search,
+    // computed in 128 bits so they can't overflow
+    weights: Vec<u128>,
+    values: Vec<u128>,
+}
+
+impl<'a> Search<'a> {
+    fn new(problem: &'a Problem) -> Self {
+        let items = &problem.given_items.list;
+        // Items which can't be packed, or which don't add any value, are left out
+        let mut order: Vec<usize> = (0..items.len())
+            .filter(|i| items[*i].value > 0 && problem.fits([&items[*i]]))
+            .collect();
+        order.sort_by(|a, b| {
+            let (a, b) = (&items[*a], &items[*b]);
+            (i128::from(b.value) * i128::from(a.weight))
+                .cmp(&(i128::from(a.value) * i128::from(b.weight)))
+        });
+
+        let prefix_sums = |f: &dyn Fn(usize) -> u128| {
+            let mut sums = vec![0];
+            for index in &order {
+                sums.push(sums.last().unwrap() + f(*index));
+            }
+            sums
+        };
+        let weights = prefix_sums(&|i| u128::from(items[i].weight));
+        let values = prefix_sums(&|i| items[i].value as u128);
+
+        Self { problem, order, weights, values }
+    }
+
+    fn item(&self, j: usize) -> &super::Item {
+        &self.problem.given_items.list[self.order[j]]
+    }
+
+    /// The packing of the items taken, given in the order of the search
+    fn knapsack(&self, taken: &[bool]) -> Result<Knapsack, String> {
+        let mut indices: Vec<usize> = taken.iter()
+            .zip(&self.order)
+            .filter_map(|(taken, index)| taken.then_some(*index))
+            .collect();
+        indices.sort();
+        Knapsack::from_indices(indices, &self.problem.given_items)
+    }
+
+    /// The Dantzig bound of packing the items from `level` onwards into the
+    /// remaining capacity, on top of what has already been packed
+    fn bound(&self, level: usize, weight: u64, value: i64) -> i64 {
+        let capacity = u128::from(self.problem.allowed_weight - weight);
+        // The critical item is the first one which doesn't fit when the items
+        // are packed in order
+        let fitting = self.weights[level + 1..]
+            .partition_point(|w| w - self.weights[level] <= capacity);
+        let critical = level + fitting;
+        let mut bound = self.values[critical] - self.values[level];
+        if critical < self.order.len() {
+            let item = self.item(critical);
+            let remaining = capacity - (self.weights[critical] - self.weights[level]);
+            bound += item.value as u128 * remaining / u128::from(item.weight);
+        }
+        // The bound never exceeds the total value of all items, so it fits
+        value + bound as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::{brute_force, small_problems};
+
+    #[test]
+    fn finds_the_optimum() {
+        for problem in small_problems() {
+            let knapsack = solve(&problem, greedy(&problem).unwrap()).unwrap();
+            assert!(problem.fits(&knapsack.items));
+            assert_eq!(knapsack.value, brute_force(&problem)[0]);
+        }
+    }
+
+    #[test]
+    fn finds_the_optimum_of_robust_and_limited_problems() {
+        for mut problem in small_problems() {
+            for (i, item) in problem.given_items.list.iter_mut().enumerate() {
+                item.deviation = item.weight / (i as u64 % 3 + 2);
+            }
+            problem.gamma = 2;
+            problem.max_items = Some(4);
+            let knapsack = solve(&problem, greedy(&problem).unwrap()).unwrap();
+            assert!(problem.fits(&knapsack.items));
+            assert_eq!(knapsack.value, brute_force(&problem)[0]);
+        }
+    }
+
+    #[test]
+    fn ranks_the_k_best_packings() {
+        for problem in small_problems() {
+            let incumbent = greedy(&problem).unwrap();
+            let ranked = solve_ranked(&problem, incumbent, 5, false).unwrap();
+            let values: Vec<i64> = ranked.iter().map(|k| k.value).collect();
+            let expected = brute_force(&problem);
+            assert_eq!(values, expected[..5.min(expected.len())]);
+            assert!(ranked.iter().all(|k| problem.fits(&k.items)));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Fully polynomial-time approximation scheme (FPTAS) for the 0-1 knapsack
+//! problem based on value scaling.
+//!
+//! The values of the items are divided by `K = epsilon * v_max / n` and
+//! rounded down, after which the scaled problem is solved exactly by dynamic
+//! programming over the total (scaled) value, computing the least weight
+//! needed to reach each value. The packing found has a value of at least
+//! (1 - epsilon) times the optimum, and the running time is O(n^3 / epsilon).
+//!
+//! [FPTAS](https://en.wikipedia.org/wiki/Knapsack_problem#Fully_polynomial_time_approximation_scheme)
+
+use super::{Knapsack, Problem};
+
+/// Upper limit on the number of bits of the dynamic programming tables, i.e.
+/// 64 bits per column for the least weights, and one bit per column and item
+/// to reconstruct the packing from (1 GiB in total)
+const MAX_TABLE_BITS: u64 = 1 << 33;
+
+pub fn solve(problem: &Problem, epsilon: f64) -> Result<Knapsack, String> {
+    if !(epsilon > 0.0 && epsilon < 1.0) {
+        return Err(format!("fptas: epsilon must be in (0, 1), was {}", epsilon));
+    }
+
+    // Items which can't be packed, or which don't add any value, are left out
+    let candidates: Vec<usize> = problem.given_items.list.iter()
+        .enumerate()
+        .filter(|(_, item)| {
+            item.value > 0 && item.weight <= problem.allowed_weight
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    let max_value = candidates.iter()
+        .map(|i| problem.given_items.list[*i].value)
+        .max();
+    let Some(max_value) = max_value else {
+        return Knapsack::from_indices(vec![], &problem.given_items);
+    };
+
+    // With a scaling factor below one, the values are kept as they are and
+    // the solution is exact
+    let scale = (epsilon * max_value as f64 / candidates.len() as f64).max(1.0);
+    let scaled: Vec<usize> = candidates.iter()
+        .map(|i| (problem.given_items.list[*i].value as f64 / scale).floor() as usize)
+        .collect();
+    // A tiny epsilon leaves the values almost as they are, so the table may
+    // need more bits than can be counted
+    let too_large = || format!("fptas: instance too large for epsilon {}", epsilon);
+    let total = scaled.iter()
+        .try_fold(0usize, |acc, p| acc.checked_add(*p))
+        .ok_or_else(too_large)?;
+    let table_bits = (total as u64).checked_add(1)
+        .and_then(|columns| columns.checked_mul(64 + candidates.len() as u64))
+        .ok_or_else(too_large)?;
+    if table_bits > MAX_TABLE_BITS {
+        return Err(format!("{} ({} bits needed)", too_large(), table_bits));
+    }
+
+    // least_weight[p] is the least weight needed to reach a scaled value of
+    // exactly p, and taken[j] marks the values p for which item j was used
+    // to improve least_weight[p]
+    let mut least_weight = vec![u64::MAX; total + 1];
+    least_weight[0] = 0;
+    let words = total / 64 + 1;
+    let mut taken = vec![vec![0u64; words]; candidates.len()];
+
+    // If the solver is cancelled, the packing is only made of the items
+    // considered so far, for which the table is complete. As the tables are
+    // bounded, considering a single item never takes long.
+    let mut considered = candidates.len();
+    for (j, (index, value)) in candidates.iter().zip(&scaled).enumerate() {
+        if problem.is_cancelled() {
+            considered = j;
+            break;
+        }
+        let weight = problem.given_items.list[*index].weight;
+        for p in (*value..=total).rev() {
+            let reached = least_weight[p - value].checked_add(weight);
+            if let Some(reached) = reached.filter(|w| *w < least_weight[p]) {
+                least_weight[p] = reached;
+                taken[j][p / 64] |= 1 << (p % 64);
+            }
+        }
+    }
+
+    let mut p = (0..=total).rev()
+        .find(|p| least_weight[*p] <= problem.allowed_weight)
+        .unwrap_or(0);
+
+    // Walk back through the items to recover the packing
+    let mut indices = vec![];
+    for j in (0..considered).rev() {
+        if taken[j][p / 64] & (1 << (p % 64)) != 0 {
+            indices.push(candidates[j]);
+            p -= scaled[j];
+        }
+    }
+    indices.reverse();
+
+    Knapsack::from_indices(indices, &problem.given_items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{GivenItems, Item};
+    use crate::solver::tests::{brute_force, small_problems};
+
+    #[test]
+    fn is_within_epsilon_of_the_optimum() {
+        for problem in small_problems() {
+            let optimum = brute_force(&problem)[0];
+            for epsilon in [0.01, 0.1, 0.5, 0.9] {
+                let knapsack = solve(&problem, epsilon).unwrap();
+                assert!(problem.fits(&knapsack.items));
+                assert!(knapsack.value as f64 >= (1.0 - epsilon) * optimum as f64,
+                        "{} is worse than (1 - {}) * {}", knapsack.value, epsilon, optimum);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_instances_whose_table_is_too_large() {
+        let items: Vec<Item> = (0..4).map(|_| Item::from(i64::MAX / 4, 1, 0)).collect();
+        let problem = Problem::new(4, GivenItems::from(items)).unwrap();
+        for epsilon in [1e-300, 1e-15] {
+            assert!(solve(&problem, epsilon).unwrap_err().contains("too large"));
+        }
+    }
+
+    /// With few items, the least weights take more memory than the bits to
+    /// reconstruct the packing from
+    #[test]
+    fn counts_the_least_weights_towards_the_table_size() {
+        let problem = Problem::new(1, GivenItems::from(vec![Item::from(8_000_000_000, 1, 0)])).unwrap();
+        assert!(solve(&problem, 1e-12).unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn rejects_epsilon_out_of_range() {
+        let problem = &small_problems()[0];
+        for epsilon in [0.0, 1.0, -0.5, f64::NAN] {
+            assert!(solve(problem, epsilon).is_err());
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! This file is almost directly copied from
+//!
+//! https://raw.githubusercontent.com/innoave/genevo/master/examples/knapsack/main.rs
+//!
+//! --------------------------------------------------------------------------------
+//! The 0-1 knapsack problem example searches for the combination of items that
+//! sums up to the greatest possible value while the total weight is still below
+//! or equal the allowed weight of the knapsack.
+//!
+//! [knapsack problem](https://en.wikipedia.org/wiki/Knapsack_problem)
+
+use std::time::Instant;
+
+use genevo::{operator::prelude::*, population::*, prelude::*};
+use smallvec::SmallVec;
+
+use super::{total, GivenItems, Knapsack, Problem, Progress};
+
+/// The genotype
+type Selection = SmallVec<[bool; 16]>;
+
+/// How do the genes of the genotype show up in the phenotype
+trait AsPhenotype {
+    fn as_knapsack(&self, given_items: &GivenItems) -> Result<Knapsack, String>;
+}
+
+impl AsPhenotype for Selection {
+    fn as_knapsack(&self, given_items: &GivenItems) -> Result<Knapsack, String> {
+        let indices = self
+            .iter()
+            .enumerate()
+            .filter_map(|(index, selected)| selected.then_some(index))
+            .collect();
+
+        Knapsack::from_indices(indices, given_items)
+    }
+}
+
+/// The fitness function for `Selection`
+impl FitnessFunction<Selection, i64> for &Problem {
+    fn fitness_of(&self, selection: &Selection) -> i64 {
+        let selected: Vec<_> = selection
+            .iter()
+            .enumerate()
+            .filter(|(_, selected)| **selected)
+            .map(|(index, _)| &self.given_items.list[index])
+            .collect();
+        // A total weight which overflows certainly exceeds the allowed weight
+        match total(selected.iter().copied()) {
+            Some((_, total_value)) if self.fits(selected) => total_value,
+            _ => 0,
+        }
+    }
+
+    fn average(&self, values: &[i64]) -> i64 {
+        (values.iter().sum::<i64>() as f32 / values.len() as f32 + 0.5).floor() as i64
+    }
+
+    fn highest_possible_fitness(&self) -> i64 {
+        self.highest_possible_fitness
+    }
+
+    fn lowest_possible_fitness(&self) -> i64 {
+        0
+    }
+}
+
+pub fn old_main(problem: &Problem) -> Result<Knapsack, String> {
+
+    let initial_population: Population<Selection> = build_population()
+        .with_genome_builder(BinaryEncodedGenomeBuilder::new(
+            problem.given_items.list.len(),
+        ))
+        .of_size(400)
+        .uniform_at_random();
+
+    // Start from the initial solution, if there is one, by letting it
+    // replace one of the random individuals
+    let initial_population = match &problem.initial_solution {
+        Some(indices) => {
+            let mut individuals = initial_population.individuals().to_vec();
+            let mut selection: Selection = SmallVec::from_elem(false, problem.given_items.list.len());
+            for index in indices {
+                selection[*index] = true;
+            }
+            individuals[0] = selection;
+            Population::with_individuals(individuals)
+        },
+        None => initial_population,
+    };
+
+    let mut knapsack_sim = simulate(
+        genetic_algorithm()
+            .with_evaluation(problem)
+            .with_selection(MaximizeSelector::new(0.85, 12))
+            .with_crossover(SinglePointCrossBreeder::new())
+            .with_mutation(RandomValueMutator::new(0.2, false, true))
+            .with_reinsertion(ElitistReinserter::new(problem, false, 0.85))
+            .with_initial_population(initial_population)
+            .build(),
+    )
+    // A solution reaching the upper bound is optimal, so there's no need to
+    // look any further
+    .until(or(FitnessLimit::new(problem.upper_bound()), GenerationLimit::new(20)))
+    .build();
+
+    // The best feasible packing of the generations so far, which is returned
+    // in the end, as the best genome of a generation may not fit. Without
+    // any, nothing is packed.
+    let mut best: Option<Knapsack> = None;
+    let best_or_empty = |best: Option<Knapsack>| match best {
+        Some(knapsack) => Ok(knapsack),
+        None => Knapsack::from_indices(vec![], &problem.given_items),
+    };
+    let start = Instant::now();
+    loop {
+        if problem.is_cancelled() {
+            return best_or_empty(best);
+        }
+
+        let result = knapsack_sim.step();
+
+        match result {
+            Ok(SimResult::Intermediate(step)) => {
+                let knapsack = step.result.best_solution
+                    .solution
+                    .genome
+                    .as_knapsack(&problem.given_items)?;
+                keep_if_better(problem, &mut best, knapsack);
+                problem.reporter.report(Progress {
+                    generation: step.iteration,
+                    best_value: best.as_ref().map(|best| best.value),
+                    elapsed: start.elapsed(),
+                });
+            },
+            Ok(SimResult::Final(step, ..)) => {
+                let knapsack = step.result.best_solution
+                    .solution
+                    .genome
+                    .as_knapsack(&problem.given_items)?;
+                keep_if_better(problem, &mut best, knapsack);
+                problem.reporter.report(Progress {
+                    generation: step.iteration,
+                    best_value: best.as_ref().map(|best| best.value),
+                    elapsed: start.elapsed(),
+                });
+                return best_or_empty(best);
+            },
+            Err(error) => {
+                eprintln!("{}", error);
+                return Err(error.to_string())
+            },
+        }
+    }
+}
+
+/// Keeps the packing as the best one if it fits and is worth more
+fn keep_if_better(problem: &Problem, best: &mut Option<Knapsack>, knapsack: Knapsack) {
+    let improves = best.as_ref().map_or(true, |best| knapsack.value > best.value);
+    if improves && problem.fits(&knapsack.items) {
+        *best = Some(knapsack);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::small_problems;
+
+    #[test]
+    fn returns_a_packing_which_fits() {
+        for problem in small_problems().into_iter().step_by(3) {
+            let knapsack = old_main(&problem).unwrap();
+            assert!(problem.fits(&knapsack.items));
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! The 0-1 knapsack problem as a mixed integer linear program (MILP), solved
+//! by the open-source solver HiGHS, which is built from source and linked
+//! statically.
+//!
+//! Each item has a binary variable `x_i`, and the program maximises
+//! `sum v_i x_i` subject to `sum w_i x_i <= C`. Unlike the other solvers, it
+//! takes any linear side constraint `sum a_i x_i <= b` of the task as just
+//! another row. Limits on the number of packed items are rows too.
+//!
+//! Robust problems use the formulation of Bertsimas and Sim: with a variable
+//! `z` for the gamma-th largest deviation of the packed items, and a variable
+//! `p_i` for how much the deviation of item i exceeds it, the capacity row
+//! becomes `sum w_i x_i + gamma z + sum p_i <= C` with `p_i + z >= d_i x_i`.
+//!
+//! [Robust knapsack](https://doi.org/10.1287/opre.1030.0065)
+
+use std::os::raw::{c_char, c_int, c_void};
+
+use highs::{Col, RowProblem, Sense};
+use highs_sys::{HighsCallbackDataIn, HighsCallbackDataOut, Highs_getModelStatus,
+                Highs_setCallback, Highs_startCallback, MODEL_STATUS_INFEASIBLE,
+                MODEL_STATUS_OPTIMAL, MODEL_STATUS_REACHED_INTERRUPT, STATUS_OK};
+
+use super::{Cancellation, Knapsack, Problem};
+
+/// `kHighsCallbackMipInterrupt` of the C API, the callback through which
+/// HiGHS asks whether to stop solving the MIP
+const CALLBACK_MIP_INTERRUPT: c_int = 6;
+
+pub fn solve(problem: &Problem, initial: Option<&Knapsack>) -> Result<Knapsack, String> {
+    // Nothing better than the initial solution is known before HiGHS starts
+    if problem.is_cancelled() {
+        return Knapsack::from_indices(vec![], &problem.given_items);
+    }
+
+    let items = &problem.given_items.list;
+    let mut lp = RowProblem::default();
+    let x: Vec<Col> = items.iter()
+        .map(|item| lp.add_integer_column(item.value as f64, 0..=1))
+        .collect();
+
+    // The capacity row, in the worst case for robust problems
+    let mut capacity: Vec<(Col, f64)> = x.iter()
+        .zip(items)
+        .map(|(x, item)| (*x, item.weight as f64))
+        .collect();
+    if problem.gamma > 0 {
+        let z = lp.add_column(0.0, 0.0..);
+        capacity.push((z, f64::from(problem.gamma)));
+        for (x, item) in x.iter().zip(items).filter(|(_, item)| item.deviation > 0) {
+            let p_i = lp.add_column(0.0, 0.0..);
+            lp.add_row(0.0.., [(p_i, 1.0), (z, 1.0), (*x, -(item.deviation as f64))]);
+            capacity.push((p_i, 1.0));
+        }
+    }
+    lp.add_row(..=problem.allowed_weight as f64, &capacity);
+
+    if let Some(max_items) = problem.max_items {
+        lp.add_row(..=max_items as f64, x.iter().map(|x| (*x, 1.0)));
+    }
+    for (c, limit) in problem.category_limits.iter().enumerate() {
+        let members = x.iter()
+            .zip(items)
+            .filter(|(_, item)| item.category == Some(c))
+            .map(|(x, _)| (*x, 1.0));
+        lp.add_row(..=*limit as f64, members);
+    }
+
+    for (j, bound) in problem.constraint_bounds.iter().enumerate() {
+        let coefficients = x.iter()
+            .zip(items)
+            .filter(|(_, item)| item.coefficients[j] != 0.0)
+            .map(|(x, item)| (*x, item.coefficients[j]));
+        lp.add_row(..=*bound, coefficients);
+    }
+
+    let mut model = lp.optimise(Sense::Maximise);
+    model.make_quiet();
+    // By default HiGHS stops within a relative gap of 1e-4 of the bound, and
+    // would report a packing as optimal which isn't
+    model.set_option("mip_rel_gap", 0.0);
+    model.set_option("mip_abs_gap", 0.0);
+
+    // The cancellation is borrowed from the problem, which outlives the solve
+    let cancellation = &problem.cancellation as *const Cancellation as *mut c_void;
+    let registered = unsafe {
+        Highs_setCallback(model.as_mut_ptr(), Some(interrupt), cancellation) == STATUS_OK
+            && Highs_startCallback(model.as_mut_ptr(), CALLBACK_MIP_INTERRUPT) == STATUS_OK
+    };
+    if !registered {
+        return Err("milp: HiGHS rejected the cancellation callback".to_string());
+    }
+    if let Some(initial) = initial {
+        let start = warm_start(problem, initial);
+        model.try_set_solution(Some(&start), None, None, None)
+            .map_err(|status| format!("milp: HiGHS rejected the initial solution: {:?}", status))?;
+    }
+
+    let solved = model.try_solve()
+        .map_err(|status| format!("milp: HiGHS failed: {:?}", status))?;
+    // The status is read directly, as the bindings don't know about
+    // interrupts. An interrupted solve keeps the best packing found so far.
+    match unsafe { Highs_getModelStatus(solved.as_ptr()) } {
+        MODEL_STATUS_OPTIMAL | MODEL_STATUS_REACHED_INTERRUPT => {},
+        MODEL_STATUS_INFEASIBLE => {
+            return Err("milp: the constraints can't be satisfied".to_string());
+        },
+        status => return Err(format!("milp: HiGHS stopped with status {}", status)),
+    }
+
+    let solution = solved.get_solution();
+    let indices = solution.columns()[..items.len()].iter()
+        .enumerate()
+        .filter(|(_, x)| **x > 0.5)
+        .map(|(i, _)| i)
+        .collect();
+    let knapsack = Knapsack::from_indices(indices, &problem.given_items)?;
+
+    // HiGHS computes with floating point numbers, so the packing is checked
+    // on the integers. If HiGHS was interrupted before it found any packing,
+    // there's nothing to check.
+    if !problem.fits(&knapsack.items) {
+        if problem.is_cancelled() {
+            return Knapsack::from_indices(vec![], &problem.given_items);
+        }
+        return Err("milp: HiGHS found a packing which doesn't fit".to_string());
+    }
+    Ok(knapsack)
+}
+
+/// Asks HiGHS to stop once the problem is cancelled
+unsafe extern "C" fn interrupt(_callback_type: c_int, _message: *const c_char,
+                               _data_out: *const HighsCallbackDataOut,
+                               data_in: *mut HighsCallbackDataIn, cancellation: *mut c_void) {
+    let cancellation = &*(cancellation as *const Cancellation);
+    if !data_in.is_null() && cancellation.is_cancelled() {
+        (*data_in).user_interrupt = 1;
+    }
+}
+
+/// The values of all columns for the initial solution, in the order they were
+/// added. For robust problems, `z` is the gamma-th largest deviation of the
+/// packed items, and `p_i` is how much the deviation of item i exceeds it.
+fn warm_start(problem: &Problem, initial: &Knapsack) -> Vec<f64> {
+    let items = &problem.given_items.list;
+    let mut start = vec![0.0; items.len()];
+    for i in &initial.indices {
+        start[*i] = 1.0;
+    }
+    if problem.gamma > 0 {
+        let mut deviations: Vec<u64> = initial.items.iter().map(|item| item.deviation).collect();
+        deviations.sort_by(|a, b| b.cmp(a));
+        let z = deviations.get(problem.gamma as usize - 1).copied().unwrap_or(0);
+        start.push(z as f64);
+        for (i, item) in items.iter().enumerate().filter(|(_, item)| item.deviation > 0) {
+            let packed = initial.indices.contains(&i);
+            start.push(if packed { item.deviation.saturating_sub(z) as f64 } else { 0.0 });
+        }
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::branch_and_bound;
+    use crate::solver::tests::{brute_force, small_problems};
+
+    fn optimum(problem: &Problem) -> Knapsack {
+        branch_and_bound::solve(problem, branch_and_bound::greedy(problem).unwrap()).unwrap()
+    }
+
+    /// The same problems with deviating weights and a limit on the number of
+    /// packed items
+    fn robust_and_limited_problems() -> Vec<Problem> {
+        small_problems().into_iter()
+            .map(|mut problem| {
+                for (i, item) in problem.given_items.list.iter_mut().enumerate() {
+                    item.deviation = item.weight / (i as u64 % 3 + 2);
+                }
+                problem.gamma = 2;
+                problem.max_items = Some(4);
+                problem
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_optimum() {
+        for problem in small_problems() {
+            let knapsack = solve(&problem, None).unwrap();
+            assert!(problem.fits(&knapsack.items));
+            assert_eq!(knapsack.value, optimum(&problem).value);
+        }
+    }
+
+    #[test]
+    fn finds_the_optimum_of_robust_and_limited_problems() {
+        for problem in robust_and_limited_problems() {
+            let knapsack = solve(&problem, None).unwrap();
+            assert!(problem.fits(&knapsack.items));
+            assert_eq!(knapsack.value, optimum(&problem).value);
+        }
+    }
+
+    #[test]
+    fn finds_the_optimum_under_linear_constraints() {
+        for mut problem in small_problems() {
+            // At most half of the weight may come from the even items
+            let half = problem.allowed_weight as f64 / 2.0;
+            for (i, item) in problem.given_items.list.iter_mut().enumerate() {
+                item.coefficients = vec![if i % 2 == 0 { item.weight as f64 } else { 0.0 }];
+            }
+            problem.constraint_bounds = vec![half];
+            let knapsack = solve(&problem, None).unwrap();
+            assert!(problem.fits(&knapsack.items));
+            assert_eq!(knapsack.value, brute_force(&problem)[0]);
+        }
+    }
+
+    #[test]
+    fn starts_from_the_initial_solution() {
+        for problem in small_problems().into_iter().chain(robust_and_limited_problems()) {
+            let optimum = optimum(&problem);
+            for initial in [branch_and_bound::greedy(&problem).unwrap(), optimum.clone()] {
+                let knapsack = solve(&problem, Some(&initial)).unwrap();
+                assert!(problem.fits(&knapsack.items));
+                assert_eq!(knapsack.value, optimum.value);
+            }
+        }
+    }
+
+    /// The columns of the warm start satisfy the rows of the robust capacity
+    #[test]
+    fn warm_start_is_feasible_in_the_worst_case() {
+        for problem in robust_and_limited_problems() {
+            let initial = optimum(&problem);
+            let items = &problem.given_items.list;
+            let start = warm_start(&problem, &initial);
+            let (x, rest) = start.split_at(items.len());
+            let (z, p) = rest.split_first().unwrap();
+
+            let deviating = items.iter().enumerate().filter(|(_, item)| item.deviation > 0);
+            assert_eq!(p.len(), deviating.clone().count());
+            for ((i, item), p_i) in deviating.zip(p) {
+                assert!(p_i + z >= item.deviation as f64 * x[i]);
+            }
+            let weight: f64 = x.iter().zip(items).map(|(x, item)| x * item.weight as f64).sum();
+            let worst_case = weight + f64::from(problem.gamma) * z + p.iter().sum::<f64>();
+            assert!(worst_case <= problem.allowed_weight as f64);
+        }
+    }
+
+    #[test]
+    fn returns_nothing_once_cancelled() {
+        let problem = &small_problems()[0];
+        problem.cancellation.cancel();
+        assert_eq!(solve(problem, None).unwrap().indices, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn interrupts_highs_once_cancelled() {
+        let cancellation = Cancellation::default();
+        let ask = |cancellation: &Cancellation| {
+            let mut data_in: HighsCallbackDataIn = unsafe { std::mem::zeroed() };
+            unsafe {
+                interrupt(CALLBACK_MIP_INTERRUPT, std::ptr::null(), std::ptr::null(),
+                          &mut data_in, cancellation as *const Cancellation as *mut c_void);
+            }
+            data_in.user_interrupt
+        };
+        assert_eq!(ask(&cancellation), 0);
+        cancellation.cancel();
+        assert_eq!(ask(&cancellation), 1);
+    }
+}
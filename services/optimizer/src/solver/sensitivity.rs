@@ -0,0 +1,182 @@
+//! Sensitivity analysis of a solved problem, answering how much the problem
+//! can change before the packing does.
+//!
+//! Reduced costs and the price of capacity come from the LP relaxation, while
+//! the value thresholds and capacity increments are exact: they are found by
+//! solving variations of the problem with the branch and bound solver, in
+//! which single items are forced in or out of the knapsack.
+//!
+//! All of it is about the packing of the solution, which is only analysed if
+//! the exact search proves it optimal, as it may be found by a heuristic. The
+//! analysis fails if it's cancelled, as the variations may not be solved
+//! exactly then.
+
+use super::{branch_and_bound, Knapsack, Problem};
+
+/// Number of capacity increments to report the optimal value for
+const CAPACITY_INCREMENTS: u64 = 5;
+
+pub struct Report {
+    pub optimal_value: i64,
+    // the value of one more unit of capacity in the LP relaxation
+    pub capacity_price: f64,
+    pub reduced_costs: Vec<f64>,
+    // packed items: the lowest value at which the item is still worth packing,
+    // other items: the value at which the item becomes worth packing, if it
+    // fits into the knapsack at all
+    pub value_thresholds: Vec<Option<i64>>,
+    // the capacity missing to add the item to the packing, for items which
+    // aren't packed
+    pub extra_capacities: Vec<Option<u64>>,
+    // pairs of capacity and the optimal value with that capacity
+    pub capacity_increments: Vec<(u64, i64)>,
+}
+
+pub fn analyse(problem: &Problem, solution: &Knapsack) -> Result<Report, String> {
+    let optimum = branch_and_bound::solve(problem, solution.clone())?;
+    check_cancelled(problem)?;
+    if optimum.value > solution.value {
+        return Err(format!("The solution of value {} isn't optimal, which is {}",
+                           solution.value, optimum.value));
+    }
+    let items = &problem.given_items.list;
+    let packed = |i: usize| solution.indices.contains(&i);
+
+    let capacity_price = capacity_price(problem);
+    let reduced_costs = items.iter()
+        .map(|item| item.value as f64 - capacity_price * item.weight as f64)
+        .collect();
+
+    let mut value_thresholds = vec![];
+    for (i, item) in items.iter().enumerate() {
+        check_cancelled(problem)?;
+        let threshold = if packed(i) {
+            // The item stops being worth packing once its value drops below
+            // what is lost by leaving it out
+            let loss = optimum.value - optimal_value_without(problem, solution, i)?;
+            Some((item.value - loss).max(0))
+        } else if item.weight <= problem.allowed_weight {
+            // The item is worth packing once its value makes up for what is
+            // lost by forcing it in
+            let loss = optimum.value - optimal_value_with(problem, i)?;
+            Some(item.value + loss)
+        } else {
+            None
+        };
+        value_thresholds.push(threshold);
+    }
+
+    let slack = problem.allowed_weight.saturating_sub(solution.weight);
+    let extra_capacities = items.iter()
+        .enumerate()
+        .map(|(i, item)| (!packed(i)).then(|| item.weight.saturating_sub(slack)))
+        .collect();
+
+    // The capacity is increased in steps of the lightest item worth packing
+    let step = items.iter()
+        .filter(|item| item.value > 0)
+        .map(|item| item.weight)
+        .min()
+        .unwrap_or(1)
+        .max(1);
+    let mut capacity_increments = vec![];
+    let mut incumbent = optimum.clone();
+    for j in 1..=CAPACITY_INCREMENTS {
+        let Some(capacity) = step.checked_mul(j)
+            .and_then(|s| s.checked_add(problem.allowed_weight))
+            .filter(|c| i64::try_from(*c).is_ok()) else { break };
+        // A bound known beforehand doesn't hold for a larger capacity
+        let larger = Problem {
+            allowed_weight: capacity,
+            known_bound: None,
+            ..problem.clone()
+        };
+        incumbent = branch_and_bound::solve(&larger, incumbent)?;
+        capacity_increments.push((capacity, incumbent.value));
+    }
+    check_cancelled(problem)?;
+
+    Ok(Report {
+        optimal_value: optimum.value,
+        capacity_price,
+        reduced_costs,
+        value_thresholds,
+        extra_capacities,
+        capacity_increments,
+    })
+}
+
+fn check_cancelled(problem: &Problem) -> Result<(), String> {
+    if problem.is_cancelled() {
+        return Err("The analysis was cancelled or ran out of time".to_string());
+    }
+    Ok(())
+}
+
+/// The optimal value of the problem when item `i` must be left out
+fn optimal_value_without(problem: &Problem, solution: &Knapsack, i: usize) -> Result<i64, String> {
+    let problem = problem.without(i);
+    let incumbent = Knapsack::from_indices(
+        solution.indices.iter().copied().filter(|j| *j != i).collect(),
+        &problem.given_items)?;
+    Ok(branch_and_bound::solve(&problem, incumbent)?.value)
+}
+
+/// The optimal value of the problem when item `i` must be packed
+fn optimal_value_with(problem: &Problem, i: usize) -> Result<i64, String> {
+    let item = &problem.given_items.list[i];
+    let rest = Problem {
+        allowed_weight: problem.allowed_weight - item.weight,
+        ..problem.without(i)
+    };
+    let incumbent = branch_and_bound::greedy(&rest)?;
+    Ok(branch_and_bound::solve(&rest, incumbent)?.value + item.value)
+}
+
+/// The value per unit of weight of the critical item, i.e. the first item
+/// which doesn't fit when packing items by decreasing value per unit of
+/// weight, or zero if all of them fit
+fn capacity_price(problem: &Problem) -> f64 {
+    let mut items: Vec<_> = problem.given_items.list.iter()
+        .filter(|item| item.value > 0)
+        .collect();
+    items.sort_by(|a, b| {
+        (i128::from(b.value) * i128::from(a.weight))
+            .cmp(&(i128::from(a.value) * i128::from(b.weight)))
+    });
+
+    let mut capacity = problem.allowed_weight;
+    for item in items {
+        if item.weight <= capacity {
+            capacity -= item.weight;
+        } else {
+            return item.value as f64 / item.weight as f64;
+        }
+    }
+    0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::small_problems;
+
+    #[test]
+    fn only_analyses_optimal_solutions() {
+        let problem = &small_problems()[0];
+        let optimum = branch_and_bound::solve(problem, branch_and_bound::greedy(problem).unwrap()).unwrap();
+        let report = analyse(problem, &optimum).unwrap();
+        assert_eq!(report.optimal_value, optimum.value);
+
+        let worse = Knapsack::from_indices(optimum.indices[1..].to_vec(), &problem.given_items).unwrap();
+        assert!(analyse(problem, &worse).is_err());
+    }
+
+    #[test]
+    fn fails_once_cancelled() {
+        let problem = &small_problems()[0];
+        let optimum = branch_and_bound::solve(problem, branch_and_bound::greedy(problem).unwrap()).unwrap();
+        problem.cancellation.cancel();
+        assert!(analyse(problem, &optimum).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Checks that a solution is consistent with the task it claims to solve, so
+//! that no solver can store an infeasible packing or a wrong total value.
+
+use std::collections::HashSet;
+
+use crate::db::models::{map_arr_inv, Solution, Task};
+use crate::solver::CONSTRAINT_TOLERANCE;
+
+pub fn verify(task: &Task, solution: &Solution) -> Result<(), String> {
+    let weights = map_arr_inv(&task.problem_weights)?;
+    let values = map_arr_inv(&task.problem_values)?;
+    let packed_items = map_arr_inv(&solution.packed_items)?;
+
+    let mut seen = HashSet::new();
+    let mut total_weight: i64 = 0;
+    let mut total_value: i64 = 0;
+    for item in packed_items {
+        let index = usize::try_from(item)
+            .ok()
+            .filter(|i| *i < weights.len() && *i < values.len())
+            .ok_or(format!("verify: item {} out of range", item))?;
+        if !seen.insert(index) {
+            return Err(format!("verify: item {} packed more than once", index));
+        }
+        total_weight = total_weight.checked_add(weights[index])
+            .ok_or("verify: total weight overflows")?;
+        total_value = total_value.checked_add(values[index])
+            .ok_or("verify: total value overflows")?;
+    }
+
+    if total_weight > task.problem_capacity {
+        return Err(format!("verify: total weight {} exceeds capacity {}",
+                           total_weight, task.problem_capacity));
+    }
+
+    // In the worst case, the gamma largest deviations of the packed items occur
+    if let (Some(deviations), Some(gamma)) = (&task.problem_deviations, task.gamma) {
+        let deviations = map_arr_inv(deviations)?;
+        let mut packed: Vec<i64> = seen.iter()
+            .map(|i| deviations.get(*i).copied()
+                 .ok_or(format!("verify: deviation of item {} missing", i)))
+            .collect::<Result<_, _>>()?;
+        packed.sort_by(|a, b| b.cmp(a));
+        let worst_case_weight = packed.iter()
+            .take(usize::try_from(gamma).unwrap_or(0))
+            .try_fold(total_weight, |acc, d| acc.checked_add(*d))
+            .ok_or("verify: worst case weight overflows")?;
+        if worst_case_weight > task.problem_capacity {
+            return Err(format!("verify: worst case weight {} exceeds capacity {}",
+                               worst_case_weight, task.problem_capacity));
+        }
+    }
+    if total_value != solution.total_value {
+        return Err(format!("verify: total value {} does not match packed items ({})",
+                           solution.total_value, total_value));
+    }
+    if let Some(max_items) = task.max_items {
+        if seen.len() > usize::try_from(max_items).unwrap_or(0) {
+            return Err(format!("verify: {} items exceed the limit of {}",
+                               seen.len(), max_items));
+        }
+    }
+
+    if let (Some(categories), Some(names), Some(limits))
+        = (&task.categories, &task.category_names, &task.category_limits)
+    {
+        for (name, limit) in names.iter().zip(map_arr_inv(limits)?) {
+            let count = seen.iter()
+                .filter(|i| categories.get(**i).is_some_and(|c| c.is_some() && c == name))
+                .count();
+            if count > usize::try_from(limit).unwrap_or(0) {
+                return Err(format!("verify: {} items of category {:?} exceed its limit of {}",
+                                   count, name, limit));
+            }
+        }
+    }
+
+    if let (Some(coefficients), Some(bounds))
+        = (&task.constraint_coefficients, &task.constraint_bounds)
+    {
+        let coefficients = map_arr_inv(coefficients)?;
+        for (j, bound) in map_arr_inv(bounds)?.into_iter().enumerate() {
+            let sum: f64 = seen.iter()
+                .map(|i| coefficients.get(j * weights.len() + i).copied()
+                     .ok_or(format!("verify: coefficient of item {} missing", i)))
+                .sum::<Result<_, _>>()?;
+            if sum > bound + CONSTRAINT_TOLERANCE * bound.abs().max(1.0) {
+                return Err(format!("verify: constraint {} sums to {}, exceeding its bound of {}",
+                                   j, sum, bound));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::db::models::map_arr;
+
+    fn task(capacity: i64, weights: &[i64], values: &[i64]) -> Task {
+        Task {
+            id: Uuid::new_v4(),
+            status: "started".to_string(),
+            ts_submitted: 0,
+            ts_started: Some(0),
+            ts_completed: None,
+            problem_capacity: capacity,
+            problem_weights: map_arr(weights),
+            problem_values: map_arr(values),
+            algorithm: "branch_and_bound".to_string(),
+            epsilon: None,
+            initial_solution: None,
+            sensitivity: false,
+            alternatives: None,
+            optimal_only: false,
+            tie_break: None,
+            parent_id: None,
+            removed_items: None,
+            inherited_bound: None,
+            problem_deviations: None,
+            gamma: None,
+            max_items: None,
+            categories: None,
+            category_names: None,
+            category_limits: None,
+            constraint_coefficients: None,
+            constraint_bounds: None,
+            keep_best: None,
+            owner: None,
+            tags: None,
+            idempotency_key: None,
+            request_hash: None,
+            callback_url: None,
+        }
+    }
+
+    fn solution(task: &Task, packed_items: &[i32], total_value: i64) -> Solution {
+        Solution {
+            id: Uuid::new_v4(),
+            packed_items: map_arr(packed_items),
+            total_value,
+            task_id: task.id,
+            upper_bound: None,
+            rank: 0,
+        }
+    }
+
+    #[test]
+    fn accepts_consistent_solutions() {
+        let task = task(10, &[4, 5, 6], &[3, 4, 5]);
+        assert_eq!(verify(&task, &solution(&task, &[0, 1], 7)), Ok(()));
+        assert_eq!(verify(&task, &solution(&task, &[], 0)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_overweight_and_malformed_packings() {
+        let task = task(10, &[4, 5, 6], &[3, 4, 5]);
+        assert!(verify(&task, &solution(&task, &[1, 2], 9)).unwrap_err().contains("exceeds capacity"));
+        assert!(verify(&task, &solution(&task, &[0, 3], 3)).unwrap_err().contains("out of range"));
+        assert!(verify(&task, &solution(&task, &[-1], 0)).unwrap_err().contains("out of range"));
+        assert!(verify(&task, &solution(&task, &[0, 0], 6)).unwrap_err().contains("more than once"));
+    }
+
+    #[test]
+    fn rejects_a_wrong_total_value() {
+        let task = task(10, &[4, 5, 6], &[3, 4, 5]);
+        assert!(verify(&task, &solution(&task, &[0, 1], 8)).unwrap_err().contains("does not match"));
+    }
+
+    #[test]
+    fn rejects_packings_which_dont_fit_in_the_worst_case() {
+        let task = Task {
+            problem_deviations: Some(map_arr(&[1, 3, 2])),
+            gamma: Some(1),
+            ..task(12, &[4, 5, 3], &[3, 4, 5])
+        };
+        // 4 + 5 + 3, and the largest deviation of 3 on top
+        assert!(verify(&task, &solution(&task, &[0, 1, 2], 12)).unwrap_err().contains("worst case"));
+        // 4 + 3, and 2 on top
+        assert_eq!(verify(&task, &solution(&task, &[0, 2], 8)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_packings_exceeding_the_item_limits() {
+        let task = Task {
+            max_items: Some(2),
+            categories: Some(vec![Some("a".to_string()), Some("a".to_string()), None]),
+            category_names: Some(vec![Some("a".to_string())]),
+            category_limits: Some(map_arr(&[1])),
+            ..task(100, &[1, 1, 1], &[1, 1, 1])
+        };
+        assert!(verify(&task, &solution(&task, &[0, 1, 2], 3)).unwrap_err().contains("limit of 2"));
+        assert!(verify(&task, &solution(&task, &[0, 1], 2)).unwrap_err().contains("category"));
+        assert_eq!(verify(&task, &solution(&task, &[1, 2], 2)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_packings_violating_a_constraint() {
+        // x0 - x1 <= 0, and x2 <= 0.5
+        let task = Task {
+            constraint_coefficients: Some(map_arr(&[1.0, -1.0, 0.0, 0.0, 0.0, 1.0])),
+            constraint_bounds: Some(map_arr(&[0.0, 0.5])),
+            ..task(100, &[1, 1, 1], &[1, 1, 1])
+        };
+        assert!(verify(&task, &solution(&task, &[0], 1)).unwrap_err().contains("constraint 0"));
+        assert!(verify(&task, &solution(&task, &[2], 1)).unwrap_err().contains("constraint 1"));
+        assert_eq!(verify(&task, &solution(&task, &[0, 1], 2)), Ok(()));
+    }
+}
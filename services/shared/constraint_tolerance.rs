@@ -0,0 +1,4 @@
+/// Slack allowed in linear constraints, relative to their bound, as the MILP
+/// solver only satisfies them up to a tolerance. Both the api and the
+/// optimizer include this file, so that they verify solutions alike.
+pub const CONSTRAINT_TOLERANCE: f64 = 1e-6;
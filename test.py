@@ -13,7 +13,7 @@ for _ in range(0,100):
 
 data = {'problem': {'capacity': capacity, 'weights': weights, 'values': values}}
 
-r = requests.post(url, data=data)
+r = requests.post(url, json=data)
 
 
 # requests.get(url + '/hhh')